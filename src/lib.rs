@@ -0,0 +1,2420 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead};
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+enum GroupItem {
+    Char(char),        // literal character
+    Range(char, char), // a-z
+    Class(PatternToken), // \d, \w, \s and their negations
+    Posix(PosixClass, bool), // [:alpha:], [:digit:], ... or negated as [:^alpha:]
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PosixClass {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    Xdigit,
+}
+
+impl PosixClass {
+    fn from_name(name: &str) -> Option<PosixClass> {
+        match name {
+            "alnum" => Some(PosixClass::Alnum),
+            "alpha" => Some(PosixClass::Alpha),
+            "blank" => Some(PosixClass::Blank),
+            "cntrl" => Some(PosixClass::Cntrl),
+            "digit" => Some(PosixClass::Digit),
+            "graph" => Some(PosixClass::Graph),
+            "lower" => Some(PosixClass::Lower),
+            "print" => Some(PosixClass::Print),
+            "punct" => Some(PosixClass::Punct),
+            "space" => Some(PosixClass::Space),
+            "upper" => Some(PosixClass::Upper),
+            "xdigit" => Some(PosixClass::Xdigit),
+            _ => None,
+        }
+    }
+
+    fn matches(self, c: char) -> bool {
+        match self {
+            PosixClass::Alnum => c.is_ascii_alphanumeric(),
+            PosixClass::Alpha => c.is_ascii_alphabetic(),
+            PosixClass::Blank => c == ' ' || c == '\t',
+            PosixClass::Cntrl => c.is_ascii_control(),
+            PosixClass::Digit => c.is_ascii_digit(),
+            PosixClass::Graph => c.is_ascii_graphic(),
+            PosixClass::Lower => c.is_ascii_lowercase(),
+            PosixClass::Print => c.is_ascii_graphic() || c == ' ',
+            PosixClass::Punct => c.is_ascii_punctuation(),
+            PosixClass::Space => is_ascii_space(c),
+            PosixClass::Upper => c.is_ascii_uppercase(),
+            PosixClass::Xdigit => c.is_ascii_hexdigit(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PatternToken {
+    Digit,          // \d
+    NonDigit,       // \D
+    Word,           // \w
+    NonWord,        // \W
+    Whitespace,     // \s
+    NonWhitespace,  // \S
+    AnyChar,        // .
+    WordBoundary,   // \b
+    WordStart,      // \<
+    WordEnd,        // \>
+    StartAnchor,    // ^ at the start of an alternative
+    EndAnchor,      // $ at the end of an alternative
+    TextStart,      // \A
+    TextEnd,        // \z
+    Char(char),     // literal character
+    CharGroup(Vec<GroupItem>, bool), // [...] or [^...]
+    Plus(Box<PatternToken>),     // token+
+    Question(Box<PatternToken>), // token?
+    Star(Box<PatternToken>),     // token*
+    Repeat { inner: Box<PatternToken>, min: usize, max: Option<usize> }, // token{n}, token{n,}, token{n,m}
+    Lazy(Box<PatternToken>),     // quantifier followed by '?', e.g. token+?
+    Possessive(Box<PatternToken>), // quantifier followed by '+', e.g. token++
+    Group(Vec<PatternToken>, Option<usize>), // (...) with its capture index, or (?:...) without one
+    Alternation(Vec<Vec<PatternToken>>), // a|b inside a group
+    Backref(usize),              // \1, \2, ...
+}
+
+// A syntax error found while tokenizing. Its position is kept as the number of
+// characters from the offending one to the end of the pattern, since that's
+// what can be told from the char iterator.
+struct SyntaxError {
+    message: &'static str,
+    remaining: usize,
+}
+
+impl SyntaxError {
+    // The character just read is at fault
+    fn at_previous(chars: &Peekable<Chars>, message: &'static str) -> SyntaxError {
+        SyntaxError { message, remaining: chars.clone().count() + 1 }
+    }
+
+    // The next character (or the end of the pattern) is at fault
+    fn at_next(chars: &Peekable<Chars>, message: &'static str) -> SyntaxError {
+        SyntaxError { message, remaining: chars.clone().count() }
+    }
+}
+
+// Tokenize a pattern that starts `offset` characters into the whole pattern.
+// `group_names` has the names (if any) of the groups already seen in earlier
+// alternatives, and is extended with the ones in this pattern. Each backreference
+// is added to `backrefs` with its position, to be checked once all the groups
+// are known.
+fn tokenize_pattern(pattern: &str, offset: usize, group_names: &mut Vec<Option<String>>, backrefs: &mut Vec<(usize, usize)>) -> Result<Vec<PatternToken>, Error> {
+    let mut chars = pattern.chars().peekable();
+    let mut found = Vec::new();
+    let position = |remaining: usize| offset + pattern.chars().count() - remaining;
+    let tokens = tokenize_sequence(&mut chars, false, group_names, &mut found).map_err(|error| Error {
+        message: error.message,
+        position: position(error.remaining),
+    })?;
+    backrefs.extend(found.into_iter().map(|(index, remaining)| (index, position(remaining))));
+    Ok(tokens)
+}
+
+// Tokenize until the end of the pattern, or until the closing ')' when inside a group.
+// A group containing '|' becomes a single Alternation token. Backreferences are
+// added to `backrefs` along with how many characters are left from their digit.
+fn tokenize_sequence(chars: &mut Peekable<Chars>, in_group: bool, group_names: &mut Vec<Option<String>>, backrefs: &mut Vec<(usize, usize)>) -> Result<Vec<PatternToken>, SyntaxError> {
+    let mut tokens = Vec::new();
+    let mut alternatives = Vec::new();
+    let mut closed = false;
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '\\' => {
+                let special = chars.next().ok_or_else(|| SyntaxError::at_previous(chars, "Trailing backslash in pattern"))?;
+                match special {
+                    'b' => PatternToken::WordBoundary,
+                    '<' => PatternToken::WordStart,
+                    '>' => PatternToken::WordEnd,
+                    'A' => PatternToken::TextStart,
+                    'z' => PatternToken::TextEnd,
+                    '1'..='9' => {
+                        let index = special as usize - '0' as usize;
+                        backrefs.push((index, chars.clone().count() + 1));
+                        PatternToken::Backref(index)
+                    },
+                    _ => match class_escape(special) {
+                        Some(class) => class,
+                        None => PatternToken::Char(escaped_char(special, chars)?),
+                    },
+                }
+            },
+            '[' => {
+                let is_negative = chars.peek() == Some(&'^');
+                if is_negative {
+                    chars.next();  // consume '^'
+                }
+                PatternToken::CharGroup(parse_group_items(chars)?, is_negative)
+            },
+            '(' if chars.peek() == Some(&'?') && chars.clone().nth(1) == Some(':') => {
+                chars.next(); // consume '?'
+                chars.next(); // consume ':'
+                PatternToken::Group(tokenize_sequence(chars, true, group_names, backrefs)?, None)
+            },
+            '(' => {
+                // Capturing groups are numbered by the order of their opening parenthesis
+                let name = parse_group_name(chars)?;
+                if name.is_some() && group_names.contains(&name) {
+                    return Err(SyntaxError::at_previous(chars, "Duplicate group name"));
+                }
+                group_names.push(name);
+                let index = group_names.len();
+                PatternToken::Group(tokenize_sequence(chars, true, group_names, backrefs)?, Some(index))
+            },
+            ')' if in_group => {
+                closed = true;
+                break;
+            },
+            ')' => return Err(SyntaxError::at_previous(chars, "Unmatched ')'")),
+            '|' if in_group => {
+                alternatives.push(std::mem::take(&mut tokens));
+                continue;
+            },
+            '.' => PatternToken::AnyChar,
+            // '^' and '$' only anchor at the start and end of an alternative, and are literals anywhere else
+            '^' if tokens.is_empty() => PatternToken::StartAnchor,
+            '$' if chars.peek().is_none() || (in_group && matches!(chars.peek(), Some(')' | '|'))) => PatternToken::EndAnchor,
+            _ => PatternToken::Char(c),
+        };
+        
+        // Check for quantifier after the token
+        let quantified = if chars.peek() == Some(&'+') {
+            chars.next(); // consume '+'
+            PatternToken::Plus(Box::new(token))
+        } else if chars.peek() == Some(&'?') {
+            chars.next(); // consume '?'
+            PatternToken::Question(Box::new(token))
+        } else if chars.peek() == Some(&'*') {
+            chars.next(); // consume '*'
+            PatternToken::Star(Box::new(token))
+        } else if chars.peek() == Some(&'{') {
+            // Only treat the brace as a quantifier if it's well-formed, otherwise it's a literal
+            let mut lookahead = chars.clone();
+            lookahead.next(); // consume '{'
+            if let Some((min, max)) = parse_counted_quantifier(&mut lookahead) {
+                if max.is_some_and(|max| min > max) {
+                    return Err(SyntaxError::at_next(chars, "Quantifier minimum is larger than its maximum"));
+                }
+                *chars = lookahead;
+                PatternToken::Repeat { inner: Box::new(token), min, max }
+            } else {
+                tokens.push(token);
+                continue;
+            }
+        } else {
+            tokens.push(token);
+            continue;
+        };
+
+        // A '?' right after a quantifier makes it lazy, and a '+' possessive
+        if chars.peek() == Some(&'?') {
+            chars.next(); // consume '?'
+            tokens.push(PatternToken::Lazy(Box::new(quantified)));
+        } else if chars.peek() == Some(&'+') {
+            chars.next(); // consume '+'
+            tokens.push(PatternToken::Possessive(Box::new(quantified)));
+        } else {
+            tokens.push(quantified);
+        }
+    }
+
+    if in_group && !closed {
+        return Err(SyntaxError::at_next(chars, "Unterminated group"));
+    }
+    if alternatives.is_empty() {
+        return Ok(tokens);
+    }
+    alternatives.push(tokens);
+    Ok(vec![PatternToken::Alternation(alternatives)])
+}
+
+// Parse the name of a group written as (?<name>...) or (?P<name>...), just
+// after its '('. A name is letters, digits and '_', not starting with a digit.
+// Other groups starting "(?" than (?:...), which the caller handles, are
+// errors, so flags like (?i) aren't mistaken for a literal '?'.
+fn parse_group_name(chars: &mut Peekable<Chars>) -> Result<Option<String>, SyntaxError> {
+    if chars.next_if_eq(&'?').is_none() {
+        return Ok(None);
+    }
+    let mut lookahead = chars.clone();
+    lookahead.next_if_eq(&'P');
+    if lookahead.next() != Some('<') {
+        return Err(SyntaxError::at_next(chars, "Unknown group flag"));
+    }
+    *chars = lookahead;
+
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) || chars.peek() != Some(&'>') {
+        return Err(SyntaxError::at_next(chars, "Invalid group name"));
+    }
+    chars.next(); // consume '>'
+    Ok(Some(name))
+}
+
+// The character an escape like \t stands for, given the character after the
+// backslash. \xHH and \u{H...} give a code point in hex, which is read from
+// `chars`. Any other escaped character, like \. or \\, stands for itself.
+fn escaped_char(c: char, chars: &mut Peekable<Chars>) -> Result<char, SyntaxError> {
+    let hex_digit = |chars: &mut Peekable<Chars>| {
+        let digit = chars.peek()?.to_digit(16)?;
+        chars.next();
+        Some(digit)
+    };
+
+    match c {
+        't' => Ok('\t'),
+        'n' => Ok('\n'),
+        'r' => Ok('\r'),
+        'f' => Ok('\x0C'),
+        'v' => Ok('\x0B'),
+        '0' => Ok('\0'),
+        'x' => {
+            // Exactly two digits, so any code point up to 0xFF
+            let mut code = 0;
+            for _ in 0..2 {
+                let digit = hex_digit(chars).ok_or_else(|| SyntaxError::at_next(chars, "Invalid hexadecimal escape"))?;
+                code = code * 16 + digit;
+            }
+            Ok(char::from(code as u8))
+        },
+        'u' => {
+            if chars.next_if_eq(&'{').is_none() {
+                return Err(SyntaxError::at_next(chars, "Invalid Unicode escape"));
+            }
+            let mut code: u32 = 0;
+            let mut digits = 0;
+            while let Some(digit) = hex_digit(chars) {
+                // Too many digits just saturate, and are rejected below
+                code = code.saturating_mul(16).saturating_add(digit);
+                digits += 1;
+            }
+            if digits == 0 || chars.next_if_eq(&'}').is_none() {
+                return Err(SyntaxError::at_next(chars, "Invalid Unicode escape"));
+            }
+            char::from_u32(code).ok_or_else(|| SyntaxError::at_previous(chars, "Unicode escape out of range"))
+        },
+        _ => Ok(c),
+    }
+}
+
+// The character class named by an escape like \d, if any
+fn class_escape(c: char) -> Option<PatternToken> {
+    match c {
+        'd' => Some(PatternToken::Digit),
+        'D' => Some(PatternToken::NonDigit),
+        'w' => Some(PatternToken::Word),
+        'W' => Some(PatternToken::NonWord),
+        's' => Some(PatternToken::Whitespace),
+        'S' => Some(PatternToken::NonWhitespace),
+        _ => None,
+    }
+}
+
+// Parse the members of a [...] group up to and including its closing ']'.
+// A '-' at the start or end of the group, or escaped as \-, is a literal hyphen.
+fn parse_group_items(chars: &mut Peekable<Chars>) -> Result<Vec<GroupItem>, SyntaxError> {
+    let mut items = Vec::new();
+
+    while let Some(item) = next_group_item(chars)? {
+        // A '-' between two characters forms a range
+        if let GroupItem::Char(low) = item {
+            if chars.peek() == Some(&'-') {
+                let mut lookahead = chars.clone();
+                lookahead.next(); // consume '-'
+                if lookahead.peek() != Some(&']') {
+                    if let Some(GroupItem::Char(high)) = next_group_item(&mut lookahead)? {
+                        if high < low {
+                            return Err(SyntaxError::at_previous(&lookahead, "Range out of order in character class"));
+                        }
+                        *chars = lookahead;
+                        items.push(GroupItem::Range(low, high));
+                        continue;
+                    }
+                }
+            }
+        }
+        items.push(item);
+    }
+    Ok(items)
+}
+
+// Read a single character or class escape from inside a [...] group. Escaped
+// characters (like \] or \\) are always literals. Returns None at the closing ']'.
+fn next_group_item(chars: &mut Peekable<Chars>) -> Result<Option<GroupItem>, SyntaxError> {
+    match chars.next() {
+        None => Err(SyntaxError::at_next(chars, "Unterminated character class")),
+        Some(']') => Ok(None),
+        Some('\\') => {
+            let escaped = chars.next().ok_or_else(|| SyntaxError::at_previous(chars, "Trailing backslash in pattern"))?;
+            match class_escape(escaped) {
+                Some(class) => Ok(Some(GroupItem::Class(class))),
+                None => Ok(Some(GroupItem::Char(escaped_char(escaped, chars)?))),
+            }
+        },
+        Some('[') if chars.peek() == Some(&':') => {
+            // A POSIX class like [:alpha:] or [:^alpha:], otherwise just a literal '['
+            let mut lookahead = chars.clone();
+            lookahead.next(); // consume ':'
+            let is_negative = lookahead.next_if_eq(&'^').is_some();
+            let mut name = String::new();
+            while let Some(c) = lookahead.next_if(|c| c.is_ascii_alphabetic()) {
+                name.push(c);
+            }
+            if lookahead.next() != Some(':') || lookahead.next() != Some(']') {
+                return Ok(Some(GroupItem::Char('[')));
+            }
+            let class = PosixClass::from_name(&name).ok_or_else(|| SyntaxError::at_previous(chars, "Unknown POSIX character class"))?;
+            *chars = lookahead;
+            Ok(Some(GroupItem::Posix(class, is_negative)))
+        },
+        Some(c) => Ok(Some(GroupItem::Char(c))),
+    }
+}
+
+// Parse the rest of a counted quantifier after its '{': "n}", "n,}", "n,m}" or
+// "{,m}" (the same as "{0,m}")
+fn parse_counted_quantifier(chars: &mut Peekable<Chars>) -> Option<(usize, Option<usize>)> {
+    fn parse_number(chars: &mut Peekable<Chars>) -> Option<usize> {
+        let mut digits = String::new();
+        while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(*c);
+            chars.next();
+        }
+        digits.parse().ok()
+    }
+
+    let min = parse_number(chars);
+    match chars.next()? {
+        '}' => Some((min?, Some(min?))),
+        ',' => {
+            let max = parse_number(chars);
+            // At least one of the bounds has to be given
+            if chars.next()? != '}' || (min.is_none() && max.is_none()) {
+                return None;
+            }
+            Some((min.unwrap_or(0), max))
+        },
+        _ => None,
+    }
+}
+
+fn contains_backref(token: &PatternToken) -> bool {
+    match token {
+        PatternToken::Backref(_) => true,
+        PatternToken::Group(group, _) => group.iter().any(contains_backref),
+        PatternToken::Alternation(alternatives) => alternatives.iter().flatten().any(contains_backref),
+        PatternToken::Plus(inner) | PatternToken::Question(inner) | PatternToken::Star(inner) => contains_backref(inner),
+        PatternToken::Repeat { inner, .. } | PatternToken::Lazy(inner) | PatternToken::Possessive(inner) => contains_backref(inner),
+        _ => false,
+    }
+}
+
+// Whether the token always consumes exactly one character when it matches
+fn consumes_single_char(token: &PatternToken) -> bool {
+    !matches!(token, PatternToken::Group(..) | PatternToken::Alternation(_) | PatternToken::Backref(_) |
+        PatternToken::WordBoundary | PatternToken::WordStart | PatternToken::WordEnd | PatternToken::StartAnchor | PatternToken::EndAnchor |
+        PatternToken::TextStart | PatternToken::TextEnd)
+}
+
+// The ranges of decimal digits (general category Nd) as of Unicode 17.0, in order.
+// char::is_numeric also takes in fractions, superscripts and Roman numerals.
+const DECIMAL_DIGITS: &[(char, char)] = &[
+    ('\u{30}', '\u{39}'), ('\u{660}', '\u{669}'), ('\u{6F0}', '\u{6F9}'), ('\u{7C0}', '\u{7C9}'),
+    ('\u{966}', '\u{96F}'), ('\u{9E6}', '\u{9EF}'), ('\u{A66}', '\u{A6F}'), ('\u{AE6}', '\u{AEF}'),
+    ('\u{B66}', '\u{B6F}'), ('\u{BE6}', '\u{BEF}'), ('\u{C66}', '\u{C6F}'), ('\u{CE6}', '\u{CEF}'),
+    ('\u{D66}', '\u{D6F}'), ('\u{DE6}', '\u{DEF}'), ('\u{E50}', '\u{E59}'), ('\u{ED0}', '\u{ED9}'),
+    ('\u{F20}', '\u{F29}'), ('\u{1040}', '\u{1049}'), ('\u{1090}', '\u{1099}'), ('\u{17E0}', '\u{17E9}'),
+    ('\u{1810}', '\u{1819}'), ('\u{1946}', '\u{194F}'), ('\u{19D0}', '\u{19D9}'), ('\u{1A80}', '\u{1A89}'),
+    ('\u{1A90}', '\u{1A99}'), ('\u{1B50}', '\u{1B59}'), ('\u{1BB0}', '\u{1BB9}'), ('\u{1C40}', '\u{1C49}'),
+    ('\u{1C50}', '\u{1C59}'), ('\u{A620}', '\u{A629}'), ('\u{A8D0}', '\u{A8D9}'), ('\u{A900}', '\u{A909}'),
+    ('\u{A9D0}', '\u{A9D9}'), ('\u{A9F0}', '\u{A9F9}'), ('\u{AA50}', '\u{AA59}'), ('\u{ABF0}', '\u{ABF9}'),
+    ('\u{FF10}', '\u{FF19}'), ('\u{104A0}', '\u{104A9}'), ('\u{10D30}', '\u{10D39}'), ('\u{10D40}', '\u{10D49}'),
+    ('\u{11066}', '\u{1106F}'), ('\u{110F0}', '\u{110F9}'), ('\u{11136}', '\u{1113F}'), ('\u{111D0}', '\u{111D9}'),
+    ('\u{112F0}', '\u{112F9}'), ('\u{11450}', '\u{11459}'), ('\u{114D0}', '\u{114D9}'), ('\u{11650}', '\u{11659}'),
+    ('\u{116C0}', '\u{116C9}'), ('\u{116D0}', '\u{116E3}'), ('\u{11730}', '\u{11739}'), ('\u{118E0}', '\u{118E9}'),
+    ('\u{11950}', '\u{11959}'), ('\u{11BF0}', '\u{11BF9}'), ('\u{11C50}', '\u{11C59}'), ('\u{11D50}', '\u{11D59}'),
+    ('\u{11DA0}', '\u{11DA9}'), ('\u{11DE0}', '\u{11DE9}'), ('\u{11F50}', '\u{11F59}'), ('\u{16130}', '\u{16139}'),
+    ('\u{16A60}', '\u{16A69}'), ('\u{16AC0}', '\u{16AC9}'), ('\u{16B50}', '\u{16B59}'), ('\u{16D70}', '\u{16D79}'),
+    ('\u{1CCF0}', '\u{1CCF9}'), ('\u{1D7CE}', '\u{1D7FF}'), ('\u{1E140}', '\u{1E149}'), ('\u{1E2F0}', '\u{1E2F9}'),
+    ('\u{1E4F0}', '\u{1E4F9}'), ('\u{1E5F1}', '\u{1E5FA}'), ('\u{1E950}', '\u{1E959}'), ('\u{1FBF0}', '\u{1FBF9}'),
+];
+
+fn is_decimal_digit(c: char) -> bool {
+    DECIMAL_DIGITS
+        .binary_search_by(|&(low, high)| if high < c { Ordering::Less } else if low > c { Ordering::Greater } else { Ordering::Equal })
+        .is_ok()
+}
+
+// \d, \w and \s only cover ASCII unless the unicode option is set
+fn is_digit(c: char, options: &MatchOptions) -> bool {
+    if options.unicode { is_decimal_digit(c) } else { c.is_ascii_digit() }
+}
+
+fn is_word_char(c: char, options: &MatchOptions) -> bool {
+    let is_alphanumeric = if options.unicode { c.is_alphanumeric() } else { c.is_ascii_alphanumeric() };
+    is_alphanumeric || c == '_' || options.word_chars.contains(c)
+}
+
+fn is_whitespace(c: char, options: &MatchOptions) -> bool {
+    if options.unicode { c.is_whitespace() } else { is_ascii_space(c) }
+}
+
+// Space, tab, newline, carriage return, form feed and vertical tab
+fn is_ascii_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0C' | '\x0B')
+}
+
+// The character `c` lowercases to: any letter with --unicode, otherwise only
+// ASCII ones. Only simple folding is done, so a letter whose lowercase takes
+// several characters (like 'İ') is left alone.
+fn to_lower(c: char, options: &MatchOptions) -> char {
+    if options.unicode { single_char(c.to_lowercase()).unwrap_or(c) } else { c.to_ascii_lowercase() }
+}
+
+// The character `c` uppercases to, folded the same way as `to_lower`, so 'ß'
+// (whose uppercase is "SS") is left alone
+fn to_upper(c: char, options: &MatchOptions) -> char {
+    if options.unicode { single_char(c.to_uppercase()).unwrap_or(c) } else { c.to_ascii_uppercase() }
+}
+
+fn single_char(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    let c = chars.next()?;
+    chars.next().is_none().then_some(c)
+}
+
+fn chars_equal(a: char, b: char, options: &MatchOptions) -> bool {
+    // Comparing uppercase too catches letters like 'ſ' that only share it with 's'
+    a == b || (options.ignore_case &&
+        (to_lower(a, options) == to_lower(b, options) || to_upper(a, options) == to_upper(b, options)))
+}
+
+// Whether `c` is any of the members of a [...] group, ignoring its negation
+fn group_contains(items: &[GroupItem], c: char, options: &MatchOptions) -> bool {
+    let is_member = |c: char| items.iter().any(|item| match item {
+        GroupItem::Char(group_char) => c == *group_char,
+        GroupItem::Range(low, high) => (*low..=*high).contains(&c),
+        GroupItem::Class(class) => matches_token(c, class, options),
+        GroupItem::Posix(class, is_negative) => class.matches(c) != *is_negative,
+    });
+    is_member(c) || (options.ignore_case &&
+        (is_member(to_lower(c, options)) || is_member(to_upper(c, options))))
+}
+
+fn matches_token(c: char, token: &PatternToken, options: &MatchOptions) -> bool {
+    match token {
+        PatternToken::Digit => is_digit(c, options),
+        PatternToken::NonDigit => !is_digit(c, options),
+        PatternToken::Word => is_word_char(c, options),
+        PatternToken::NonWord => !is_word_char(c, options),
+        PatternToken::Whitespace => is_whitespace(c, options),
+        PatternToken::NonWhitespace => !is_whitespace(c, options),
+        PatternToken::AnyChar => c != '\n' || options.dot_matches_newline,
+        PatternToken::Char(pattern_char) => chars_equal(c, *pattern_char, options),
+        // Negation applies to the group as a whole, not to each of its members
+        PatternToken::CharGroup(items, is_negative) => group_contains(items, c, options) != *is_negative,
+        PatternToken::Plus(_) => false, // This should not be called directly
+        PatternToken::Question(_) => false, // This should not be called directly
+        PatternToken::Star(_) => false, // This should not be called directly
+        PatternToken::Repeat { .. } => false, // This should not be called directly
+        PatternToken::Lazy(_) => false, // This should not be called directly
+        PatternToken::Possessive(_) => false, // This should not be called directly
+        PatternToken::Group(..) => false, // This should not be called directly
+        PatternToken::Alternation(_) => false, // This should not be called directly
+        PatternToken::Backref(_) => false, // This should not be called directly
+        PatternToken::WordBoundary => false, // This should not be called directly
+        PatternToken::WordStart => false, // This should not be called directly
+        PatternToken::WordEnd => false, // This should not be called directly
+        PatternToken::StartAnchor => false, // This should not be called directly
+        PatternToken::EndAnchor => false, // This should not be called directly
+        PatternToken::TextStart => false, // This should not be called directly
+        PatternToken::TextEnd => false, // This should not be called directly
+    }
+}
+
+/// Settings that change how the pattern matches text
+#[derive(Debug, Clone, Default)]
+pub struct MatchOptions {
+    /// Compare letters without regard to case. Only ASCII letters are folded
+    /// unless `unicode` is set too.
+    pub ignore_case: bool,
+    /// Only accept matches with no word character directly before or after them
+    pub word_regexp: bool,
+    /// Only accept matches spanning the whole line, as if anchored with `^` and `$`
+    pub line_regexp: bool,
+    /// Treat `\n` like any other character, so a trailing one is part of the
+    /// text rather than ignored before `$`
+    pub newline_is_ordinary: bool,
+    /// Let `.` match `\n` too
+    pub dot_matches_newline: bool,
+    /// Let `^` and `$` match just after and before each `\n` in the text too,
+    /// not only at its start and end. `\A` and `\z` still only match there.
+    pub multi_line: bool,
+    /// Let `\d`, `\w` and `\s` (and word boundaries) match any Unicode digit,
+    /// word character or whitespace rather than only ASCII ones, and let
+    /// `ignore_case` fold the case of any letter
+    pub unicode: bool,
+    /// Match the pattern as literal text, with no special characters
+    pub fixed_string: bool,
+    /// Read the pattern as a basic regular expression, where `+`, `?`, `{`,
+    /// `}`, `(`, `)` and `|` are literal unless escaped with a backslash
+    pub basic_syntax: bool,
+    /// Extra characters counted as word characters by `\w`, `\b`, `\<`,
+    /// `\>` and `word_regexp`, besides letters, digits and '_'
+    pub word_chars: String,
+}
+
+// The text being matched along with the options to match it under. Positions
+// are byte offsets into `text` and always fall on char boundaries.
+struct Input<'a> {
+    text: &'a str,
+    options: &'a MatchOptions,
+    // Whether a line ending was cut off the end of `text`, so the very end of
+    // what was given, where \z matches, is out of reach
+    cut_line_ending: bool,
+}
+
+impl Input<'_> {
+    fn char_at(&self, pos: usize) -> Option<char> {
+        self.text[pos..].chars().next()
+    }
+
+    fn char_before(&self, pos: usize) -> Option<char> {
+        self.text[..pos].chars().next_back()
+    }
+
+    // Whether there's a word character just before or at `pos`, with the edges
+    // of the text counting as non-word
+    fn word_char_before(&self, pos: usize) -> bool {
+        self.char_before(pos).is_some_and(|c| is_word_char(c, self.options))
+    }
+
+    fn word_char_at(&self, pos: usize) -> bool {
+        self.char_at(pos).is_some_and(|c| is_word_char(c, self.options))
+    }
+}
+
+// Spans of the text matched by each capturing group, indexed by group number
+type GroupSpans = Vec<Option<(usize, usize)>>;
+
+// A reference compared and hashed by the address it points to, so the same
+// tokens in different places of the pattern are told apart
+struct ByAddress<'a, T: ?Sized>(&'a T);
+
+impl<T: ?Sized> Clone for ByAddress<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for ByAddress<'_, T> {}
+
+impl<T: ?Sized> PartialEq for ByAddress<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl<T: ?Sized> Eq for ByAddress<'_, T> {}
+
+impl<T: ?Sized> Hash for ByAddress<'_, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.0, state)
+    }
+}
+
+// A point to go on matching from: tokens[token_idx..], followed by whatever
+// the continuation numbered `context` does with where they end
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Resume<'t> {
+    tokens: ByAddress<'t, [PatternToken]>,
+    token_idx: usize,
+    context: usize,
+}
+
+// What a continuation does with the position it's given, which is all that
+// decides whether it matches when there are no backreferences
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Then<'t> {
+    // Hand the end to the caller of match_tokens_at_position
+    Accept,
+    // Take the end of a possessive repetition as it is
+    Settle,
+    Resume(Resume<'t>),
+    // Repeat `inner` again or stop, after `count` repetitions of which the last
+    // started at `start`, then go on to the continuation numbered `after`.
+    // Without a maximum, counts past the minimum all behave alike.
+    Repeat { inner: ByAddress<'t, PatternToken>, count: usize, start: usize, after: usize },
+}
+
+// Mutable state threaded through the backtracking matcher
+struct MatchState<'t> {
+    captures: GroupSpans,
+    memo: Option<Memo<'t>>,
+}
+
+// The points already known not to match from a position. Only kept when the
+// pattern has no backreferences, since otherwise the outcome also depends on
+// what the groups captured. Without them it doesn't depend on where the match
+// started either, so the memo is kept across all the start positions tried.
+//
+// Each distinct continuation is numbered, so a point inside a group is only
+// skipped when what follows the group is the same too. That keeps matching
+// polynomial in the length of the text, even for nested repetitions like
+// (a+)+ or (a|aa)*, though each level of nesting raises the degree.
+struct Memo<'t> {
+    contexts: HashMap<Then<'t>, usize>,
+    failed: HashSet<(Resume<'t>, usize)>,
+}
+
+// Receives the position where the preceding tokens stopped matching and tries the rest
+type Continuation<'c, 't> = dyn FnMut(usize, &mut MatchState<'t>) -> Option<usize> + 'c;
+
+// The continuation number of Then::Accept
+const ACCEPT: usize = 0;
+
+impl<'t> MatchState<'t> {
+    fn new(memoize: bool) -> MatchState<'t> {
+        MatchState {
+            captures: GroupSpans::new(),
+            memo: memoize.then(|| Memo {
+                contexts: HashMap::from([(Then::Accept, ACCEPT)]),
+                failed: HashSet::new(),
+            }),
+        }
+    }
+
+    // The number of the continuation that does `then`. Without a memo they
+    // needn't be told apart.
+    fn context(&mut self, then: Then<'t>) -> usize {
+        match &mut self.memo {
+            Some(memo) => {
+                let next = memo.contexts.len();
+                *memo.contexts.entry(then).or_insert(next)
+            },
+            None => ACCEPT,
+        }
+    }
+
+    fn has_failed(&self, resume: Resume<'t>, pos: usize) -> bool {
+        self.memo.as_ref().is_some_and(|memo| memo.failed.contains(&(resume, pos)))
+    }
+}
+
+// Match `tokens` starting exactly at `start_pos`, returning where the match ends.
+// Only end positions that `accept_end` allows are considered, backtracking
+// into the tokens otherwise. The captures in `state` are reset first, but the
+// failures it remembers from earlier start positions still apply, so `state`
+// must only be reused for the same tokens and `accept_end`.
+fn match_tokens_at_position<'t>(input: &Input, tokens: &'t [PatternToken], start_pos: usize, state: &mut MatchState<'t>, accept_end: &dyn Fn(usize) -> bool) -> Option<usize> {
+    state.captures.clear();
+    backtrack_match(input, tokens, start_pos, 0, ACCEPT, state, &mut |end_pos, _| accept_end(end_pos).then_some(end_pos))
+}
+
+// Match tokens[token_idx..] at `pos` and hand the end to `cont`, whose number
+// is `context`. Points that have already failed are skipped, so repeated
+// visits don't cost exponential time.
+fn backtrack_match<'t>(input: &Input, tokens: &'t [PatternToken], pos: usize, token_idx: usize, context: usize, state: &mut MatchState<'t>, cont: &mut Continuation<'_, 't>) -> Option<usize> {
+    let resume = Resume { tokens: ByAddress(tokens), token_idx, context };
+    if state.has_failed(resume, pos) {
+        return None;
+    }
+
+    let result = match_token(input, tokens, pos, token_idx, context, state, cont);
+    if let Some(memo) = state.memo.as_mut().filter(|_| result.is_none()) {
+        memo.failed.insert((resume, pos));
+        // An unbounded repetition of one character can only end somewhere in
+        // the run of matching characters it starts on, so starting later in
+        // that run has no ends left that haven't just failed
+        if let Some(quantifier) = tokens.get(token_idx).and_then(char_run_quantifier) {
+            let mut next = pos;
+            while let Some(c) = input.char_at(next).filter(|&c| matches_token(c, quantifier.inner, input.options)) {
+                next += c.len_utf8();
+                if !memo.failed.insert((resume, next)) {
+                    break;
+                }
+            }
+        }
+    }
+    result
+}
+
+// A greedy or lazy quantifier with no upper bound on a single character, like
+// a* or \d+?, whose failures the memo can extend along a run of characters
+fn char_run_quantifier(token: &PatternToken) -> Option<Quantifier<'_>> {
+    as_quantifier(token)
+        .filter(|q| q.max.is_none() && q.greediness != Greediness::Possessive && consumes_single_char(q.inner))
+}
+
+// How a quantifier chooses between more and fewer repetitions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Greediness {
+    Greedy, // as many as possible first
+    Lazy,   // as few as possible first
+    Possessive, // as many as possible, never giving any back
+}
+
+// A quantifier token broken down into what it repeats and how
+struct Quantifier<'a> {
+    inner: &'a PatternToken,
+    min: usize,
+    max: Option<usize>,
+    greediness: Greediness,
+}
+
+impl Quantifier<'_> {
+    fn allows_more(&self, count: usize) -> bool {
+        self.max.map_or(true, |max| count < max)
+    }
+}
+
+fn as_quantifier(token: &PatternToken) -> Option<Quantifier<'_>> {
+    let (inner, min, max) = match token {
+        PatternToken::Plus(inner) => (inner, 1, None),
+        PatternToken::Question(inner) => (inner, 0, Some(1)),
+        PatternToken::Star(inner) => (inner, 0, None),
+        PatternToken::Repeat { inner, min, max } => (inner, *min, *max),
+        PatternToken::Lazy(quantified) => {
+            let quantifier = as_quantifier(quantified)?;
+            return Some(Quantifier { greediness: Greediness::Lazy, ..quantifier });
+        },
+        PatternToken::Possessive(quantified) => {
+            let quantifier = as_quantifier(quantified)?;
+            return Some(Quantifier { greediness: Greediness::Possessive, ..quantifier });
+        },
+        _ => return None,
+    };
+    Some(Quantifier { inner, min, max, greediness: Greediness::Greedy })
+}
+
+fn match_token<'t>(input: &Input, tokens: &'t [PatternToken], pos: usize, token_idx: usize, context: usize, state: &mut MatchState<'t>, cont: &mut Continuation<'_, 't>) -> Option<usize> {
+    if token_idx >= tokens.len() {
+        return cont(pos, state);
+    }
+
+    if let Some(quantifier) = as_quantifier(&tokens[token_idx]) {
+        if quantifier.greediness == Greediness::Possessive {
+            return repeat_possessive(input, &quantifier, pos, state, &mut |end, state| {
+                backtrack_match(input, tokens, end, token_idx + 1, context, state, cont)
+            });
+        }
+        if !consumes_single_char(quantifier.inner) {
+            let after = state.context(Then::Resume(Resume { tokens: ByAddress(tokens), token_idx: token_idx + 1, context }));
+            return repeat_group(input, &quantifier, 0, pos, after, state, &mut |end, state| {
+                backtrack_match(input, tokens, end, token_idx + 1, context, state, cont)
+            });
+        }
+        let mut rest = |end, state: &mut MatchState<'t>| backtrack_match(input, tokens, end, token_idx + 1, context, state, cont);
+        // If the same quantifier already failed one character further on, every
+        // count above the minimum was covered there and only the minimum is left
+        let resume = Resume { tokens: ByAddress(tokens), token_idx, context };
+        let covered = quantifier.max.is_none()
+            && input.char_at(pos).is_some_and(|c| {
+                matches_token(c, quantifier.inner, input.options) && state.has_failed(resume, pos + c.len_utf8())
+            });
+        if covered {
+            let quantifier = Quantifier { max: Some(quantifier.min), ..quantifier };
+            return repeat_char_greedy(input, &quantifier, pos, state, &mut rest);
+        }
+        return match quantifier.greediness {
+            Greediness::Greedy => repeat_char_greedy(input, &quantifier, pos, state, &mut rest),
+            Greediness::Lazy => repeat_char_lazy(input, &quantifier, pos, state, &mut rest),
+            Greediness::Possessive => unreachable!("possessive quantifiers are handled above"),
+        };
+    }
+
+    // Where a group or alternative inside this token goes on to once it ends
+    let mut rest_context = || state.context(Then::Resume(Resume { tokens: ByAddress(tokens), token_idx: token_idx + 1, context }));
+    match &tokens[token_idx] {
+        PatternToken::Group(group, None) => {
+            let inner = rest_context();
+            backtrack_match(input, group, pos, 0, inner, state, &mut |group_end, state| {
+                backtrack_match(input, tokens, group_end, token_idx + 1, context, state, cont)
+            })
+        },
+        PatternToken::Group(group, Some(index)) => {
+            let inner = rest_context();
+            backtrack_match(input, group, pos, 0, inner, state, &mut |group_end, state| {
+                if state.captures.len() <= *index {
+                    state.captures.resize(*index + 1, None);
+                }
+                // Record the capture, restoring the previous one if the rest fails to match
+                let previous = state.captures[*index];
+                state.captures[*index] = Some((pos, group_end));
+                let result = backtrack_match(input, tokens, group_end, token_idx + 1, context, state, cont);
+                if result.is_none() {
+                    state.captures[*index] = previous;
+                }
+                result
+            })
+        },
+        PatternToken::Alternation(alternatives) => {
+            // Try each alternative in order, moving on when the rest of the pattern fails after it
+            let inner = rest_context();
+            alternatives.iter().find_map(|alternative| {
+                backtrack_match(input, alternative, pos, 0, inner, state, &mut |alternative_end, state| {
+                    backtrack_match(input, tokens, alternative_end, token_idx + 1, context, state, cont)
+                })
+            })
+        },
+        PatternToken::Backref(index) => {
+            // A group that hasn't matched yet can't be referenced
+            let (start, end) = state.captures.get(*index).copied().flatten()?;
+            let mut end_pos = pos;
+            for captured_char in input.text[start..end].chars() {
+                match input.char_at(end_pos) {
+                    Some(c) if chars_equal(c, captured_char, input.options) => end_pos += c.len_utf8(),
+                    _ => return None,
+                }
+            }
+            backtrack_match(input, tokens, end_pos, token_idx + 1, context, state, cont)
+        },
+        PatternToken::WordStart => {
+            // Zero-width: a word character must follow, and none precede
+            if input.word_char_before(pos) || !input.word_char_at(pos) {
+                return None;
+            }
+            backtrack_match(input, tokens, pos, token_idx + 1, context, state, cont)
+        },
+        PatternToken::WordEnd => {
+            // Zero-width: a word character must precede, and none follow
+            if !input.word_char_before(pos) || input.word_char_at(pos) {
+                return None;
+            }
+            backtrack_match(input, tokens, pos, token_idx + 1, context, state, cont)
+        },
+        PatternToken::StartAnchor => {
+            // A multi-line text can hold several lines, and ^ matches at the
+            // start of each of them
+            let line_start = pos == 0 || (input.options.multi_line && input.char_before(pos) == Some('\n'));
+            if !line_start {
+                return None;
+            }
+            backtrack_match(input, tokens, pos, token_idx + 1, context, state, cont)
+        },
+        PatternToken::EndAnchor => {
+            let line_end = pos == input.text.len() || (input.options.multi_line && input.char_at(pos) == Some('\n'));
+            if !line_end {
+                return None;
+            }
+            backtrack_match(input, tokens, pos, token_idx + 1, context, state, cont)
+        },
+        PatternToken::TextStart => {
+            if pos != 0 {
+                return None;
+            }
+            backtrack_match(input, tokens, pos, token_idx + 1, context, state, cont)
+        },
+        PatternToken::TextEnd => {
+            // A line ending that was cut off still comes before the very end
+            if pos != input.text.len() || input.cut_line_ending {
+                return None;
+            }
+            backtrack_match(input, tokens, pos, token_idx + 1, context, state, cont)
+        },
+        PatternToken::WordBoundary => {
+            // Zero-width: the characters on either side must differ in word-ness,
+            // with the edges of the input counting as non-word
+            if input.word_char_before(pos) == input.word_char_at(pos) {
+                return None;
+            }
+            backtrack_match(input, tokens, pos, token_idx + 1, context, state, cont)
+        },
+        token => match input.char_at(pos) {
+            Some(c) if matches_token(c, token, input.options) => {
+                backtrack_match(input, tokens, pos + c.len_utf8(), token_idx + 1, context, state, cont)
+            },
+            _ => None,
+        },
+    }
+}
+
+// Match a single-char token as many times as allowed, then give the characters
+// back one at a time until `cont` accepts the position
+fn repeat_char_greedy<'t>(input: &Input, quantifier: &Quantifier, pos: usize, state: &mut MatchState<'t>, cont: &mut Continuation<'_, 't>) -> Option<usize> {
+    let mut end = pos;
+    let mut count = 0;
+    while quantifier.allows_more(count) {
+        match input.char_at(end) {
+            Some(c) if matches_token(c, quantifier.inner, input.options) => {
+                end += c.len_utf8();
+                count += 1;
+            },
+            _ => break,
+        }
+    }
+    if count < quantifier.min {
+        return None;
+    }
+
+    loop {
+        if let Some(final_pos) = cont(end, state) {
+            return Some(final_pos);
+        }
+        if count == quantifier.min {
+            return None;
+        }
+        end -= input.char_before(end)?.len_utf8();
+        count -= 1;
+    }
+}
+
+// Match a single-char token as few times as allowed, taking one more character
+// at a time until `cont` accepts the position
+fn repeat_char_lazy<'t>(input: &Input, quantifier: &Quantifier, pos: usize, state: &mut MatchState<'t>, cont: &mut Continuation<'_, 't>) -> Option<usize> {
+    let mut end = pos;
+    let mut count = 0;
+
+    loop {
+        if count >= quantifier.min {
+            if let Some(final_pos) = cont(end, state) {
+                return Some(final_pos);
+            }
+        }
+        if !quantifier.allows_more(count) {
+            return None;
+        }
+        match input.char_at(end) {
+            Some(c) if matches_token(c, quantifier.inner, input.options) => {
+                end += c.len_utf8();
+                count += 1;
+            },
+            _ => return None,
+        }
+    }
+}
+
+// Match a quantified group (or other multi-char token) `count` or more times. Greedy
+// quantifiers try one more repetition before handing the current position to `cont`,
+// lazy ones the other way around. `after` is the number of `cont`.
+fn repeat_group<'t>(input: &Input, quantifier: &Quantifier<'t>, count: usize, pos: usize, after: usize, state: &mut MatchState<'t>, cont: &mut Continuation<'_, 't>) -> Option<usize> {
+    let satisfied = count >= quantifier.min;
+    if satisfied && quantifier.greediness == Greediness::Lazy {
+        if let Some(final_pos) = cont(pos, state) {
+            return Some(final_pos);
+        }
+    }
+
+    if quantifier.allows_more(count) {
+        let group = quantifier.inner;
+        let counted = if quantifier.max.is_some() { count } else { count.min(quantifier.min) };
+        let context = state.context(Then::Repeat { inner: ByAddress(group), count: counted, start: pos, after });
+        let result = backtrack_match(input, std::slice::from_ref(group), pos, 0, context, state, &mut |group_end, state| {
+            // An empty repetition can't make progress, so once the minimum is met it
+            // ends the repeating, and the rest of the pattern goes on from there
+            if group_end == pos && satisfied {
+                return cont(group_end, state);
+            }
+            repeat_group(input, quantifier, count + 1, group_end, after, state, cont)
+        });
+        if result.is_some() {
+            return result;
+        }
+    }
+
+    if satisfied && quantifier.greediness == Greediness::Greedy {
+        cont(pos, state)
+    } else {
+        None
+    }
+}
+
+// Match as many repetitions as a greedy quantifier would, then hand only that
+// position to `cont`. If the rest fails, fewer repetitions aren't tried.
+fn repeat_possessive<'t>(input: &Input, quantifier: &Quantifier<'t>, pos: usize, state: &mut MatchState<'t>, cont: &mut Continuation<'_, 't>) -> Option<usize> {
+    let previous = state.captures.clone();
+    let greedy = Quantifier { greediness: Greediness::Greedy, ..*quantifier };
+    let mut settle = |end, _: &mut MatchState<'t>| Some(end);
+    let end = if consumes_single_char(greedy.inner) {
+        repeat_char_greedy(input, &greedy, pos, state, &mut settle)
+    } else {
+        let settled = state.context(Then::Settle);
+        repeat_group(input, &greedy, 0, pos, settled, state, &mut settle)
+    }?;
+
+    let result = cont(end, state);
+    if result.is_none() {
+        state.captures = previous;
+    }
+    result
+}
+
+// Split a pattern on unescaped top-level '|' (ignoring any inside [...] or (...))
+fn split_alternatives(pattern: &str) -> Vec<&str> {
+    let mut branches = Vec::new();
+    let mut branch_start = 0;
+    let mut in_group = false;
+    let mut depth = 0;
+    let mut chars = pattern.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next(); // skip the escaped character
+            },
+            '[' if in_group => {
+                // Skip a POSIX class so its ']' doesn't end the group
+                if let Some(len) = posix_class_len(chars.clone().map(|(_, c)| c)) {
+                    chars.nth(len - 1);
+                }
+            },
+            '[' => in_group = true,
+            ']' => in_group = false,
+            '(' if !in_group => depth += 1,
+            ')' if !in_group && depth > 0 => depth -= 1,
+            '|' if !in_group && depth == 0 => {
+                branches.push(&pattern[branch_start..i]);
+                branch_start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    branches.push(&pattern[branch_start..]);
+    branches
+}
+
+// How many characters after a '[' in a group complete a POSIX class like
+// [:alpha:] or [:^alpha:], following the same rules as next_group_item
+fn posix_class_len(rest: impl Iterator<Item = char>) -> Option<usize> {
+    let mut rest = rest.peekable();
+    rest.next_if_eq(&':')?;
+    let mut len = 1 + usize::from(rest.next_if_eq(&'^').is_some());
+    while rest.next_if(char::is_ascii_alphabetic).is_some() {
+        len += 1;
+    }
+    (rest.next() == Some(':') && rest.next() == Some(']')).then_some(len + 2)
+}
+
+// Rewrite a basic pattern in the extended syntax the tokenizer reads, swapping
+// the meanings of `+ ? { } ( ) |` with their escaped forms outside [...].
+// Also returns, for each character of the rewritten pattern and for its end,
+// the position in `pattern` it came from, so errors can point into the original.
+fn basic_to_extended(pattern: &str) -> (String, Vec<usize>) {
+    let swapped = |c: char| matches!(c, '+' | '?' | '{' | '}' | '(' | ')' | '|');
+    let mut extended = String::new();
+    let mut positions = Vec::new();
+    let mut in_group = false;
+    let mut chars = pattern.chars().enumerate().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, escaped)) if !in_group && swapped(escaped) => {
+                    extended.push(escaped);
+                    positions.push(i);
+                },
+                // Other escapes mean the same in both syntaxes, including the
+                // braces of \u{...}
+                Some((j, escaped)) => {
+                    extended.extend(['\\', escaped]);
+                    positions.extend([i, j]);
+                    if escaped == 'u' && chars.peek().is_some_and(|&(_, c)| c == '{') {
+                        for (k, c) in chars.by_ref() {
+                            extended.push(c);
+                            positions.push(k);
+                            if c == '}' {
+                                break;
+                            }
+                        }
+                    }
+                },
+                None => {
+                    extended.push('\\');
+                    positions.push(i);
+                },
+            },
+            '[' if in_group => {
+                // Copy a POSIX class whole so its ']' doesn't end the group
+                let len = posix_class_len(chars.clone().map(|(_, c)| c)).unwrap_or(0);
+                extended.push(c);
+                positions.push(i);
+                for (k, c) in chars.by_ref().take(len) {
+                    extended.push(c);
+                    positions.push(k);
+                }
+            },
+            _ if !in_group && swapped(c) => {
+                extended.extend(['\\', c]);
+                positions.extend([i, i]);
+            },
+            _ => {
+                match c {
+                    '[' => in_group = true,
+                    ']' => in_group = false,
+                    _ => {}
+                }
+                extended.push(c);
+                positions.push(i);
+            },
+        }
+    }
+    positions.push(pattern.chars().count());
+    (extended, positions)
+}
+
+// Remove a single trailing "\n" or "\r\n", keeping any other trailing whitespace
+fn strip_line_ending(input_line: &str) -> &str {
+    match input_line.strip_suffix('\n') {
+        Some(line) => line.strip_suffix('\r').unwrap_or(line),
+        None => input_line,
+    }
+}
+
+/// An error in the syntax of a pattern
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message} at position {position}")]
+pub struct Error {
+    message: &'static str,
+    position: usize,
+}
+
+impl Error {
+    /// A description of what's wrong with the pattern
+    pub fn message(&self) -> &'static str {
+        self.message
+    }
+
+    /// The index of the character in the pattern where parsing failed
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+/// The location of a match, as `start..end` byte offsets into the searched text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+}
+
+// A top-level alternative of a pattern, tokenized
+#[derive(Debug)]
+struct Branch {
+    tokens: Vec<PatternToken>,
+    // Whether matches can only start at the start of the text (or end at its end)
+    start_anchored: bool,
+    end_anchored: bool,
+    has_backrefs: bool,
+    // Set when the branch is nothing but literal text
+    literal: Option<Literal>,
+}
+
+// A branch of plain characters, possibly anchored, which can be checked with
+// a substring search instead of the backtracking matcher
+#[derive(Debug)]
+struct Literal {
+    text: String,
+    start_anchored: bool,
+    end_anchored: bool,
+}
+
+impl Literal {
+    // Only plain comparisons are done, so options like -i and -w rule this out,
+    // as do anchors that can match in the middle of the text at a newline
+    fn from_tokens(tokens: &[PatternToken], options: &MatchOptions) -> Option<Literal> {
+        if options.ignore_case || options.word_regexp {
+            return None;
+        }
+        let single_line = !options.multi_line;
+        let (start_anchored, tokens) = match tokens {
+            [PatternToken::StartAnchor, rest @ ..] if single_line => (true, rest),
+            _ => (false, tokens),
+        };
+        let (end_anchored, tokens) = match tokens {
+            [rest @ .., PatternToken::EndAnchor] if single_line => (true, rest),
+            _ => (false, tokens),
+        };
+        let text = tokens
+            .iter()
+            .map(|token| match token {
+                PatternToken::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect::<Option<String>>()?;
+        Some(Literal {
+            text,
+            start_anchored: start_anchored || options.line_regexp,
+            end_anchored: end_anchored || options.line_regexp,
+        })
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        match (self.start_anchored, self.end_anchored) {
+            (true, true) => text == self.text,
+            (true, false) => text.starts_with(&self.text),
+            (false, true) => text.ends_with(&self.text),
+            (false, false) => text.contains(&self.text),
+        }
+    }
+}
+
+/// A pattern compiled once up front so it can be matched against many lines.
+///
+/// Each text is matched as a single line. One trailing `\n` or `\r\n` is
+/// ignored, so `$` matches at the end of the line whether or not it's there.
+/// `\z` doesn't, since the text only really ends after it.
+///
+/// Its `Debug` output shows how the pattern was parsed.
+#[derive(Debug)]
+pub struct Regex {
+    branches: Vec<Branch>,
+    options: MatchOptions,
+    // The name of each capturing group, if it has one, in order
+    group_names: Arc<[Option<String>]>,
+}
+
+impl Regex {
+    /// Compile `pattern` with the default options
+    pub fn new(pattern: &str) -> Result<Regex, Error> {
+        Regex::with_options(pattern, MatchOptions::default())
+    }
+
+    /// Compile `pattern`, matching it according to `options`
+    pub fn with_options(pattern: &str, options: MatchOptions) -> Result<Regex, Error> {
+        // A basic pattern is rewritten in the extended syntax first
+        let basic = (options.basic_syntax && !options.fixed_string).then(|| basic_to_extended(pattern));
+        let pattern = basic.as_ref().map_or(pattern, |(extended, _)| extended.as_str());
+        // Groups are numbered across the whole pattern, not per alternative
+        let mut group_names = Vec::new();
+        // Each backreference's group number and position, checked once all the
+        // groups have been seen
+        let mut backrefs = Vec::new();
+        // Where the current alternative starts in the pattern, in characters
+        let mut offset = 0;
+        // A fixed string is a single alternative of nothing but literal characters
+        let alternatives = if options.fixed_string { vec![pattern] } else { split_alternatives(pattern) };
+        let branches: Vec<Branch> = alternatives
+            .into_iter()
+            .map(|branch| {
+                let tokens = if options.fixed_string {
+                    branch.chars().map(PatternToken::Char).collect()
+                } else {
+                    tokenize_pattern(branch, offset, &mut group_names, &mut backrefs)?
+                };
+                offset += branch.chars().count() + 1; // skip the '|' too
+                // -x anchors both ends, whether or not the pattern already does. A
+                // leading '\A' (or '^' when the text is a single line) also means
+                // only the start of the text needs trying.
+                let start_anchored = options.line_regexp || match tokens.first() {
+                    Some(PatternToken::TextStart) => true,
+                    Some(PatternToken::StartAnchor) => !options.multi_line,
+                    _ => false,
+                };
+                let end_anchored = options.line_regexp;
+                let has_backrefs = tokens.iter().any(contains_backref);
+                let literal = Literal::from_tokens(&tokens, &options);
+                Ok(Branch { tokens, start_anchored, end_anchored, has_backrefs, literal })
+            })
+            .collect::<Result<_, Error>>()
+            .and_then(|branches| match backrefs.iter().find(|&&(index, _)| index > group_names.len()) {
+                // A reference to a group that doesn't exist could never match
+                Some(&(_, position)) => Err(Error { message: "Invalid back reference", position }),
+                None => Ok(branches),
+            })
+            .map_err(|error| match &basic {
+                // Point at the character the error came from in the original pattern
+                Some((_, positions)) => Error { position: positions[error.position], ..error },
+                None => error,
+            })?;
+
+        Ok(Regex { branches, options, group_names: group_names.into() })
+    }
+
+    /// Whether the pattern matches anywhere in `text`
+    pub fn is_match(&self, text: &str) -> bool {
+        // Purely literal patterns only need a substring search
+        if self.branches.iter().all(|branch| branch.literal.is_some()) {
+            let text = self.line(text);
+            return self.branches.iter().filter_map(|branch| branch.literal.as_ref()).any(|literal| literal.is_match(text));
+        }
+        self.find(text).is_some()
+    }
+
+    /// Find the leftmost match in `text`
+    pub fn find(&self, text: &str) -> Option<Match> {
+        let input = self.input(text);
+
+        self.find_from(&input, 0).map(|(found, _)| found)
+    }
+
+    /// Find the leftmost match in `text` along with the spans of its capturing groups
+    pub fn captures<'t>(&self, text: &'t str) -> Option<Captures<'t>> {
+        let input = self.input(text);
+        let (found, spans) = self.find_from(&input, 0)?;
+
+        Some(self.make_captures(self.line(text), found, spans))
+    }
+
+    /// Iterate over the captures of every non-overlapping match in `text`, like [`Regex::find_iter`]
+    pub fn captures_iter<'r, 't>(&'r self, text: &'t str) -> CaptureMatches<'r, 't> {
+        CaptureMatches { matches: self.find_iter(text) }
+    }
+
+    fn make_captures<'t>(&self, text: &'t str, found: Match, mut spans: GroupSpans) -> Captures<'t> {
+        // Groups that never matched may be missing from the end
+        spans.resize(self.group_names.len() + 1, None);
+        spans[0] = Some((found.start, found.end));
+        Captures { text, spans, group_names: Arc::clone(&self.group_names) }
+    }
+
+    /// Iterate over every non-overlapping match in `text`, left to right.
+    /// Empty matches are included, except directly after the previous match.
+    pub fn find_iter<'r, 't>(&'r self, text: &'t str) -> Matches<'r, 't> {
+        let line = self.line(text);
+        Matches {
+            regex: self,
+            text: line,
+            cut_line_ending: line.len() < text.len(),
+            from: 0,
+            last_end: None,
+        }
+    }
+
+    /// Match the pattern starting exactly at byte offset `pos` in `text`, returning
+    /// where the match ends. Unlike [`Regex::find`], no later start positions are
+    /// tried. The text before `pos` still counts, though: `^` only matches at
+    /// offset 0, not at `pos`, and `\b` looks at the character before `pos`.
+    ///
+    /// This is the method to use for anchored matching, such as a lexer asking
+    /// whether a token starts right where the last one ended, which other
+    /// libraries call `find_at`.
+    pub fn matches_at(&self, text: &str, pos: usize) -> Option<usize> {
+        let input = self.input(text);
+        if !input.text.is_char_boundary(pos) {
+            return None;
+        }
+
+        // The first alternative to match wins, as with find
+        self.branches.iter().find_map(|branch| match_branch(&input, branch, pos, false)).map(|(found, _)| found.end)
+    }
+
+    /// Whether the pattern always matches exactly one character regardless of
+    /// what's around it, like `a`, `.` or `[0-9]`. Text can then be checked one
+    /// character at a time with [`Regex::matches_char`], without collecting lines.
+    pub fn is_single_char(&self) -> bool {
+        !self.options.word_regexp && !self.options.line_regexp &&
+            self.branches.iter().all(|branch| match branch.tokens.as_slice() {
+                [token] => consumes_single_char(token) && as_quantifier(token).is_none(),
+                _ => false,
+            })
+    }
+
+    /// Whether `c` matches a pattern that [`Regex::is_single_char`] holds for
+    pub fn matches_char(&self, c: char) -> bool {
+        self.branches.iter().any(|branch| matches_token(c, &branch.tokens[0], &self.options))
+    }
+
+    /// Replace every non-overlapping match in `text` with `replacement`, copying
+    /// the text between matches as is. In `replacement`, `$0` stands for the
+    /// whole match, `$1`, `$2`, ... for what those groups captured (nothing if
+    /// they didn't match), `${name}` for what a named group captured and `$$`
+    /// for a literal `$`. `${1}` can separate a group number from digits after it.
+    pub fn replace_all(&self, text: &str, replacement: &str) -> String {
+        let mut replaced = String::new();
+        let mut last_end = 0;
+        let mut matches = self.find_iter(text);
+
+        while let Some((found, spans)) = matches.next_with_spans() {
+            replaced.push_str(&text[last_end..found.start]);
+            expand_replacement(&mut replaced, replacement, text, found, &spans, &self.group_names);
+            last_end = found.end;
+        }
+        replaced.push_str(&text[last_end..]);
+        replaced
+    }
+
+    /// Split `text` into the pieces between non-overlapping matches. Matches at
+    /// the very start or end, or right next to each other, leave empty pieces.
+    pub fn split<'t>(&self, text: &'t str) -> Vec<&'t str> {
+        let mut pieces = Vec::new();
+        let mut last_end = 0;
+
+        for found in self.find_iter(text) {
+            pieces.push(&text[last_end..found.start]);
+            last_end = found.end;
+        }
+        pieces.push(&text[last_end..]);
+        pieces
+    }
+
+    /// Read `reader` a line at a time, yielding the 1-based number and text of
+    /// each line that matches. Lines are only read as the iterator is advanced.
+    /// A read error (including a line that isn't valid UTF-8) is yielded as is,
+    /// and iteration may carry on with the following lines.
+    pub fn search_lines<R: BufRead>(&self, reader: R) -> LineMatches<'_, R> {
+        LineMatches { regex: self, lines: reader.lines(), line_number: 0 }
+    }
+
+    // The part of `text` to match, without its line ending unless newlines are ordinary
+    fn line<'t>(&self, text: &'t str) -> &'t str {
+        if self.options.newline_is_ordinary { text } else { strip_line_ending(text) }
+    }
+
+    fn input<'a>(&'a self, text: &'a str) -> Input<'a> {
+        let line = self.line(text);
+        Input { text: line, options: &self.options, cut_line_ending: line.len() < text.len() }
+    }
+
+    // Find the leftmost match starting at or after `from` across all alternatives,
+    // with earlier alternatives winning ties
+    fn find_from(&self, input: &Input, from: usize) -> Option<(Match, GroupSpans)> {
+        if from > input.text.len() {
+            return None;
+        }
+
+        let mut leftmost: Option<(Match, GroupSpans)> = None;
+        for branch in &self.branches {
+            if let Some((found, spans)) = match_branch(input, branch, from, true) {
+                if leftmost.as_ref().map_or(true, |(best, _)| found.start < best.start) {
+                    leftmost = Some((found, spans));
+                }
+            }
+        }
+        leftmost
+    }
+}
+
+/// The spans matched by a pattern and its capturing groups, created by [`Regex::captures`]
+pub struct Captures<'t> {
+    text: &'t str,
+    // Indexed by group number, with the whole match as group 0
+    spans: GroupSpans,
+    group_names: Arc<[Option<String>]>,
+}
+
+impl<'t> Captures<'t> {
+    /// The `start..end` byte offsets matched by group `i`, where group 0 is the
+    /// whole match. None if the group didn't take part in the match or doesn't exist.
+    /// A repeated group, as in `(ab){2,3}`, keeps what its last repetition matched.
+    pub fn get(&self, i: usize) -> Option<(usize, usize)> {
+        self.spans.get(i).copied().flatten()
+    }
+
+    /// The text matched by group `i`, like [`Captures::get`]
+    pub fn get_str(&self, i: usize) -> Option<&'t str> {
+        self.get(i).map(|(start, end)| &self.text[start..end])
+    }
+
+    /// The `start..end` byte offsets matched by the group named `name`, as in
+    /// `(?<name>...)`. None if there's no such group or it didn't take part in the match.
+    pub fn get_by_name(&self, name: &str) -> Option<(usize, usize)> {
+        self.get(group_index(&self.group_names, name)?)
+    }
+}
+
+/// An iterator over the matches in a text, created by [`Regex::find_iter`]
+pub struct Matches<'r, 't> {
+    regex: &'r Regex,
+    text: &'t str,
+    cut_line_ending: bool,
+    from: usize,
+    last_end: Option<usize>,
+}
+
+impl Matches<'_, '_> {
+    fn next_with_spans(&mut self) -> Option<(Match, GroupSpans)> {
+        loop {
+            let input = Input { text: self.text, options: &self.regex.options, cut_line_ending: self.cut_line_ending };
+            let (found, spans) = self.regex.find_from(&input, self.from)?;
+
+            if found.end > found.start {
+                self.from = found.end;
+            } else {
+                // Step past empty matches so the search always makes progress
+                self.from = found.end + input.char_at(found.end).map_or(1, char::len_utf8);
+                if self.last_end == Some(found.end) {
+                    continue;
+                }
+            }
+            self.last_end = Some(found.end);
+            return Some((found, spans));
+        }
+    }
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        self.next_with_spans().map(|(found, _)| found)
+    }
+}
+
+/// An iterator over the captures of each match in a text, created by [`Regex::captures_iter`]
+pub struct CaptureMatches<'r, 't> {
+    matches: Matches<'r, 't>,
+}
+
+impl<'t> Iterator for CaptureMatches<'_, 't> {
+    type Item = Captures<'t>;
+
+    fn next(&mut self) -> Option<Captures<'t>> {
+        let (found, spans) = self.matches.next_with_spans()?;
+        Some(self.matches.regex.make_captures(self.matches.text, found, spans))
+    }
+}
+
+/// An iterator over the matching lines read from a reader, created by [`Regex::search_lines`]
+pub struct LineMatches<'r, R> {
+    regex: &'r Regex,
+    lines: io::Lines<R>,
+    line_number: usize,
+}
+
+impl<R: BufRead> Iterator for LineMatches<'_, R> {
+    type Item = io::Result<(usize, String)>;
+
+    fn next(&mut self) -> Option<io::Result<(usize, String)>> {
+        loop {
+            let line = self.lines.next()?;
+            // A line that fails to read still counts towards the numbering
+            self.line_number += 1;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            if self.regex.is_match(&line) {
+                return Some(Ok((self.line_number, line)));
+            }
+        }
+    }
+}
+
+// Append `replacement` to `replaced`, substituting $0, $1, ... (or ${1},
+// ${name}) with the text `found` and its groups matched, and $$ with '$'. A
+// '$' followed by anything else is kept as is.
+fn expand_replacement(replaced: &mut String, replacement: &str, text: &str, found: Match, spans: &GroupSpans, group_names: &[Option<String>]) {
+    let mut chars = replacement.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            replaced.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'$').is_some() {
+            replaced.push('$');
+            continue;
+        }
+
+        // ${name} stands for a named group and ${N} for a numbered one
+        let index = if chars.peek() == Some(&'{') {
+            let mut lookahead = chars.clone();
+            lookahead.next(); // consume '{'
+            let mut reference = String::new();
+            while let Some(c) = lookahead.next_if(|&c| c != '}') {
+                reference.push(c);
+            }
+            // Without its closing brace, it's just a '$'
+            if lookahead.next() != Some('}') {
+                replaced.push('$');
+                continue;
+            }
+            chars = lookahead;
+            reference.parse().ok().or_else(|| group_index(group_names, &reference))
+        } else {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+            match digits.parse() {
+                Ok(index) => Some(index),
+                Err(_) => {
+                    replaced.push('$');
+                    continue;
+                },
+            }
+        };
+        match index {
+            Some(0) => replaced.push_str(&text[found.start..found.end]),
+            Some(index) => {
+                if let Some((start, end)) = spans.get(index).copied().flatten() {
+                    replaced.push_str(&text[start..end]);
+                }
+            },
+            // An unknown name stands for nothing, like a group that didn't match
+            None => {},
+        }
+    }
+}
+
+// The number of the group named `name`, if there is one
+fn group_index(group_names: &[Option<String>], name: &str) -> Option<usize> {
+    group_names.iter().position(|group_name| group_name.as_deref() == Some(name)).map(|i| i + 1)
+}
+
+// Find the leftmost match of a single branch that starts at or after `from`
+// (or only exactly at `from` without `scan`), along with the spans its groups captured
+fn match_branch(input: &Input, branch: &Branch, from: usize, scan: bool) -> Option<(Match, GroupSpans)> {
+    if branch.start_anchored && from > 0 {
+        return None;
+    }
+
+    // With -w, the match can't be directly preceded or followed by a word character
+    let word_regexp = input.options.word_regexp;
+    let accept_start = |pos: usize| !word_regexp || !input.word_char_before(pos);
+    let accept_end = |pos: usize| {
+        // If end-anchored, ensure we matched exactly to the end
+        (!branch.end_anchored || pos == input.text.len()) &&
+            (!word_regexp || !input.word_char_at(pos))
+    };
+
+    // Try matching at each char boundary from `from` onwards (only the first when start-anchored)
+    let mut state = MatchState::new(!branch.has_backrefs);
+    let mut start_pos = from;
+    loop {
+        if accept_start(start_pos) {
+            if let Some(end_pos) = match_tokens_at_position(input, &branch.tokens, start_pos, &mut state, &accept_end) {
+                return Some((Match { start: start_pos, end: end_pos }, std::mem::take(&mut state.captures)));
+            }
+        }
+        if branch.start_anchored || !scan {
+            return None;
+        }
+        start_pos += input.char_at(start_pos)?.len_utf8();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The span of the first match of `pattern` in `text`
+    fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+        Regex::new(pattern).unwrap().find(text).map(|m| (m.start, m.end))
+    }
+
+    #[test]
+    fn posix_class_inside_group_with_alternation() {
+        let regex = Regex::new("[[:digit:]|x]").unwrap();
+        assert!(regex.is_match("|"));
+        assert!(regex.is_match("7"));
+        assert!(!regex.is_match("y"));
+
+        let regex = Regex::new("[[:digit:](]|x").unwrap();
+        assert!(regex.is_match("("));
+        assert!(regex.is_match("x"));
+        assert!(!regex.is_match("|"));
+
+        let basic = MatchOptions { basic_syntax: true, ..MatchOptions::default() };
+        let regex = Regex::with_options("[[:digit:]|x]", basic).unwrap();
+        assert!(regex.is_match("|"));
+        assert!(!regex.is_match("y"));
+    }
+
+    #[test]
+    fn unicode_digits_are_decimal_digits_only() {
+        let unicode = MatchOptions { unicode: true, ..MatchOptions::default() };
+        let regex = Regex::with_options(r"^\d+$", unicode).unwrap();
+        for digits in ["0123456789", "٣٤", "४२", "１２", "𝟘𝟡"] {
+            assert!(regex.is_match(digits), "{}", digits);
+        }
+        for numeric in ["½", "²", "Ⅷ", "①"] {
+            assert!(!regex.is_match(numeric), "{}", numeric);
+        }
+        assert!(!Regex::new(r"\d").unwrap().is_match("٣"));
+    }
+
+    #[test]
+    fn unknown_group_flags_are_errors() {
+        let error = Regex::new("(?i)x").unwrap_err();
+        assert_eq!((error.message(), error.position()), ("Unknown group flag", 2));
+        assert_eq!(Regex::new("a(?").unwrap_err().message(), "Unknown group flag");
+        assert_eq!(Regex::new("(?Px)").unwrap_err().message(), "Unknown group flag");
+        assert!(Regex::new("(?:x)(?<a>y)(?P<b>z)").unwrap().is_match("xyz"));
+    }
+
+    #[test]
+    fn matches_at_only_tries_the_given_offset() {
+        let regex = Regex::new(r"\d+").unwrap();
+        assert_eq!(regex.matches_at("ab 123", 3), Some(6));
+        assert_eq!(regex.matches_at("ab 123", 4), Some(6));
+        assert_eq!(regex.matches_at("ab 123", 0), None);
+        assert_eq!(regex.matches_at("ab 123", 2), None);
+        // Offsets that aren't on a character boundary never match
+        assert_eq!(Regex::new(".").unwrap().matches_at("é", 1), None);
+    }
+
+    #[test]
+    fn stacked_stars_fail_quickly_on_long_lines() {
+        let regex = Regex::new("a*a*a*b").unwrap();
+        let text = "a".repeat(20_000);
+        assert!(regex.find(&text).is_none());
+        assert_eq!(regex.find(&format!("{}b", text)).map(|m| (m.start, m.end)), Some((0, 20_001)));
+    }
+
+    #[test]
+    fn memo_handles_rejected_ends() {
+        // Matches that -w or a final $ turns down fail past the last token
+        let word = MatchOptions { word_regexp: true, ..MatchOptions::default() };
+        let regex = Regex::with_options("c.t", word).unwrap();
+        assert_eq!(regex.find("category cat").map(|m| (m.start, m.end)), Some((9, 12)));
+        assert_eq!(find("c.t$", "cat cut"), Some((4, 7)));
+    }
+
+    #[test]
+    fn memo_keeps_lazy_and_greedy_results() {
+        let regex = Regex::new("a*a+b").unwrap();
+        assert_eq!(regex.find("xaaab").map(|m| (m.start, m.end)), Some((1, 5)));
+        let regex = Regex::new("a+?a*?b").unwrap();
+        assert_eq!(regex.find("caab").map(|m| (m.start, m.end)), Some((1, 4)));
+        assert!(!regex.is_match("bb"));
+    }
+
+    #[test]
+    fn star_matches_zero_or_more() {
+        assert_eq!(find("go*gle", "ggle"), Some((0, 4)));
+        assert_eq!(find("go*gle", "gooogle"), Some((0, 7)));
+        assert_eq!(find("a*", ""), Some((0, 0)));
+        assert_eq!(find("x[abc]*y", "xcabby"), Some((0, 6)));
+        assert_eq!(find(r"\d*x", "12x"), Some((0, 3)));
+        // Greedy, but gives characters back for the rest of the pattern
+        assert_eq!(find("a*ab", "aaab"), Some((0, 4)));
+    }
+
+    #[test]
+    fn dot_matches_any_character() {
+        assert_eq!(find("c.t", "cat"), Some((0, 3)));
+        assert_eq!(find("c.t", "ct"), None);
+        assert_eq!(find("a.+b", "axyzb"), Some((0, 5)));
+        assert_eq!(find("a.+b", "ab"), None);
+        assert_eq!(find("x.?y", "xy"), Some((0, 2)));
+        assert_eq!(find(r"a\.b", "axb"), None);
+        assert_eq!(find(r"a\.b", "a.b"), Some((0, 3)));
+    }
+
+    #[test]
+    fn alternation_matches_either_branch() {
+        assert_eq!(find("cat|dog", "I have a dog"), Some((9, 12)));
+        assert_eq!(find("cat|dog", "a cat"), Some((2, 5)));
+        assert_eq!(find("cat|dog", "a cow"), None);
+        // Anchors belong to their own branch
+        assert_eq!(find("^cat|dog$", "dog cat"), None);
+        assert_eq!(find("^cat|dog$", "cat dog"), Some((0, 3)));
+        // An empty branch matches the empty string
+        assert_eq!(find("a|", "xyz"), Some((0, 0)));
+    }
+
+    #[test]
+    fn quantified_groups() {
+        assert_eq!(find("(ab)+", "ababab"), Some((0, 6)));
+        assert_eq!(find("x(ab)?y", "xy"), Some((0, 2)));
+        assert_eq!(find("x(ab)*y", "xababy"), Some((0, 6)));
+        assert_eq!(find("((ab)c)+", "abcabcab"), Some((0, 6)));
+        // The group has to try its other alternative for the rest to match
+        assert_eq!(find("(a|ab)+c", "ababc"), Some((0, 5)));
+        assert_eq!(find("(a+)+b", "aaab"), Some((0, 4)));
+        assert!(Regex::new("(ab").is_err());
+    }
+
+    #[test]
+    fn backreferences_match_captured_text() {
+        assert_eq!(find(r"(cat) and \1", "cat and cat"), Some((0, 11)));
+        assert_eq!(find(r"(cat) and \1", "cat and dog"), None);
+        assert_eq!(find(r"(\w+) (\w+) \2 \1", "one two two one"), Some((0, 15)));
+        // Captures are undone when the matcher backtracks
+        assert_eq!(find(r"(a+)b\1", "aaba"), Some((1, 4)));
+        // A group that hasn't matched yet matches nothing
+        assert_eq!(find(r"\1(a)", "aa"), None);
+        assert_eq!(find(r"(x)?y\1", "y"), None);
+    }
+
+    #[test]
+    fn counted_quantifiers() {
+        assert_eq!(find("^xx{0,2}$", "x"), Some((0, 1)));
+        assert_eq!(find("^x{0,2}$", ""), Some((0, 0)));
+        assert_eq!(find("^x{0,2}$", "xx"), Some((0, 2)));
+        assert_eq!(find("^x{0,2}$", "xxx"), None);
+        assert_eq!(find("a{3}", "aaaa"), Some((0, 3)));
+        assert_eq!(find("a{3}", "aa"), None);
+        assert_eq!(find("a{2,}", "aaaaa"), Some((0, 5)));
+        assert_eq!(find("ba{1,3}", "baaaa"), Some((0, 4)));
+        // A brace that doesn't start a count is a literal
+        assert_eq!(find("a{", "a{"), Some((0, 2)));
+        assert_eq!(find("a{x}", "a{x}"), Some((0, 4)));
+    }
+
+    #[test]
+    fn character_ranges() {
+        assert_eq!(find("[0-9]+", "abc 42"), Some((4, 6)));
+        assert_eq!(find("[^a-f]", "abcxef"), Some((3, 4)));
+        assert_eq!(find("[a-zA-Z0-9_]+", "-Foo_9-"), Some((1, 6)));
+        // A '-' at either end is a literal hyphen
+        assert_eq!(find("[-a]+", "x-a-"), Some((1, 4)));
+        assert_eq!(find("[a-]+", "x-a-"), Some((1, 4)));
+        assert_eq!(find("[a-c]", "-"), None);
+        assert!(Regex::new("[z-a]").is_err());
+    }
+
+    #[test]
+    fn whitespace_classes() {
+        assert_eq!(find(r"hello\sworld", "hello world"), Some((0, 11)));
+        assert_eq!(find(r"hello\sworld", "hello_world"), None);
+        assert_eq!(find(r"a\s+b", "a \t\r\x0b\x0cb"), Some((0, 7)));
+        assert_eq!(find(r"\S+", "  word  "), Some((2, 6)));
+        assert_eq!(find(r"\S", " \t"), None);
+    }
+
+    #[test]
+    fn negated_digit_and_word_classes() {
+        for c in ["a", " ", ".", "Z"] {
+            assert!(Regex::new(r"\D").unwrap().is_match(c), "{}", c);
+        }
+        assert!(!Regex::new(r"\D").unwrap().is_match("0123456789"));
+        assert_eq!(find(r"\d\D\d", "1-2"), Some((0, 3)));
+        assert_eq!(find(r"\d\D\d", "123"), None);
+        assert_eq!(find(r"\W+", "foo, bar"), Some((3, 5)));
+        assert!(!Regex::new(r"\W").unwrap().is_match("abc_123"));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert_eq!(find(r"\bcat\b", "the cat sat"), Some((4, 7)));
+        assert_eq!(find(r"\bcat\b", "category"), None);
+        assert_eq!(find(r"\bcat\b", "bobcat"), None);
+        // The edges of the text count as non-word characters
+        assert_eq!(find(r"\bcat\b", "cat"), Some((0, 3)));
+        assert_eq!(find(r"\b", ""), None);
+        assert_eq!(find(r"\b", " "), None);
+    }
+
+    #[test]
+    fn match_spans() {
+        assert_eq!(find(r"\d+", "abc123def456"), Some((3, 6)));
+        assert_eq!(find("^abc", "abcabc"), Some((0, 3)));
+        assert_eq!(find("abc$", "abcabc"), Some((3, 6)));
+        assert_eq!(find("b+", "abbbc"), Some((1, 4)));
+        // Spans are byte offsets, so they slice the text directly
+        assert_eq!(find("b", "ébé"), Some((2, 3)));
+        assert_eq!(find("x", "abc"), None);
+    }
+
+    #[test]
+    fn anchors_are_literals_elsewhere() {
+        assert_eq!(find("a$b", "a$b"), Some((0, 3)));
+        assert_eq!(find("a^b", "a^b"), Some((0, 3)));
+        assert_eq!(find(r"\$5", "costs $5"), Some((6, 8)));
+        assert_eq!(find(r"^\^", "^x"), Some((0, 1)));
+        assert_eq!(find(r"x\$", "x$"), Some((0, 2)));
+        assert_eq!(find("x$", "x$"), None);
+    }
+
+    #[test]
+    fn escaped_backslash() {
+        assert_eq!(find(r"a\\b", r"a\b"), Some((0, 3)));
+        assert_eq!(find(r"a\\b", "ab"), None);
+        assert_eq!(find(r"\\+", r"x\\y"), Some((1, 3)));
+        let error = Regex::new(r"a\").unwrap_err();
+        assert_eq!((error.message(), error.position()), ("Trailing backslash in pattern", 1));
+    }
+
+    #[test]
+    fn escapes_inside_groups() {
+        assert_eq!(find(r"[a\]b]+", "x]ab"), Some((1, 4)));
+        assert_eq!(find(r"[\\]", r"a\b"), Some((1, 2)));
+        assert_eq!(find(r"[a\-z]+", "b-az"), Some((1, 4)));
+        assert_eq!(find(r"[\^]", "a^"), Some((1, 2)));
+        assert_eq!(find(r"[\d.]+", "v1.25!"), Some((1, 5)));
+        assert_eq!(find(r"[\w]+", "--ab_1--"), Some((2, 6)));
+    }
+
+    #[test]
+    fn regex_basics() {
+        let regex = Regex::new(r"\d+ apples?").unwrap();
+        assert!(regex.is_match("I have 3 apples"));
+        assert!(!regex.is_match("I have no apples"));
+        assert_eq!(regex.find("buy 12 apple"), Some(Match { start: 4, end: 12 }));
+        assert_eq!(regex.find("none"), None);
+
+        let error = Regex::new("a(b").unwrap_err();
+        assert_eq!(error.to_string(), format!("{} at position {}", error.message(), error.position()));
+    }
+
+    #[test]
+    fn find_iter_yields_each_match() {
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            Regex::new(pattern).unwrap().find_iter(text).map(|m| (m.start, m.end)).collect()
+        };
+        assert_eq!(spans(r"\d+", "12 34 56"), [(0, 2), (3, 5), (6, 8)]);
+        assert_eq!(spans("aa", "aaaaa"), [(0, 2), (2, 4)]);
+        // Empty matches move on by a character so they can't repeat forever
+        assert_eq!(spans("a*", "baab"), [(0, 0), (1, 3), (4, 4)]);
+        assert_eq!(spans("x*", "éé"), [(0, 0), (2, 2), (4, 4)]);
+    }
+
+    #[test]
+    fn compiled_once_matches_many_lines() {
+        // The compiled form can be shared, like across threads searching files
+        fn assert_shareable<T: Send + Sync>() {}
+        assert_shareable::<Regex>();
+
+        let regex = Regex::new("^[a-c]+$").unwrap();
+        let lines = ["abc", "abd", "", "cab"];
+        let matched: Vec<_> = lines.iter().filter(|line| regex.is_match(line)).collect();
+        assert_eq!(matched, [&"abc", &"cab"]);
+
+        let found: Vec<_> = regex.search_lines("abc\nabd\ncab\n".as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(found, [(1, "abc".to_string()), (3, "cab".to_string())]);
+    }
+
+    #[test]
+    fn large_multibyte_input() {
+        let text = "héllo wörld 😀 ".repeat(20_000) + "ünïcode";
+        let regex = Regex::new("w.rld .").unwrap();
+        let matches: Vec<_> = regex.find_iter(&text).collect();
+        assert_eq!(matches.len(), 20_000);
+        for found in &matches {
+            assert_eq!(&text[found.start..found.end], "wörld 😀");
+        }
+        assert_eq!(Regex::new(r"ü\w+$").unwrap().find(&text).map(|m| &text[m.start..m.end]), None);
+        let unicode = MatchOptions { unicode: true, ..MatchOptions::default() };
+        let regex = Regex::with_options(r"ü\w+$", unicode).unwrap();
+        assert_eq!(regex.find(&text).map(|m| &text[m.start..m.end]), Some("ünïcode"));
+    }
+
+    #[test]
+    fn lazy_quantifiers() {
+        let text = "<a><b>";
+        assert_eq!(find("<.+>", text), Some((0, 6)));
+        assert_eq!(find("<.+?>", text), Some((0, 3)));
+        assert_eq!(find("a+", "aaa"), Some((0, 3)));
+        assert_eq!(find("a+?", "aaa"), Some((0, 1)));
+        assert_eq!(find("a*?", "aaa"), Some((0, 0)));
+        assert_eq!(find("ab??", "ab"), Some((0, 1)));
+        assert_eq!(find("ab?", "ab"), Some((0, 2)));
+        // Lazy repetition still takes more when the rest needs it
+        assert_eq!(find("a+?b", "aaab"), Some((0, 4)));
+    }
+
+    #[test]
+    fn posix_classes() {
+        assert_eq!(find("[[:upper:]]+", "abcDEFghi"), Some((3, 6)));
+        assert_eq!(find("[[:upper:]]", "abc123"), None);
+        assert_eq!(find("[[:digit:]a-f]+", "xyz0af9g"), Some((3, 7)));
+        assert_eq!(find("[[:alpha:]]+", "12ab34"), Some((2, 4)));
+        assert_eq!(find("[[:space:]]", "a\tb"), Some((1, 2)));
+        assert_eq!(find("[[:alnum:]]+", "--a1--"), Some((2, 4)));
+        assert_eq!(find("[[:^digit:]]+", "12ab34"), Some((2, 4)));
+        assert_eq!(Regex::new("[[:nope:]]").unwrap_err().message(), "Unknown POSIX character class");
+    }
+
+    #[test]
+    fn captured_groups() {
+        let regex = Regex::new(r"(\d+)-(\d+)").unwrap();
+        let captures = regex.captures("from 10-250 on").unwrap();
+        assert_eq!(captures.get(0), Some((5, 11)));
+        assert_eq!(captures.get(1), Some((5, 7)));
+        assert_eq!(captures.get_str(1), Some("10"));
+        assert_eq!(captures.get_str(2), Some("250"));
+        assert_eq!(captures.get(3), None);
+        assert!(regex.captures("no numbers").is_none());
+
+        // A group that took no part in the match has no span
+        let captures = Regex::new("(a)|(b)").unwrap().captures("b").unwrap();
+        assert_eq!(captures.get(1), None);
+        assert_eq!(captures.get_str(2), Some("b"));
+    }
+
+    #[test]
+    fn replace_all_substitutes_groups() {
+        let regex = Regex::new(r"(\w+)@(\w+)").unwrap();
+        assert_eq!(regex.replace_all("mail bob@example or amy@site!", "$2.$1"), "mail example.bob or site.amy!");
+        assert_eq!(regex.replace_all("bob@example", "<$0>"), "<bob@example>");
+        assert_eq!(regex.replace_all("bob@example", "$$1"), "$1");
+        assert_eq!(regex.replace_all("no address", "$1"), "no address");
+        assert_eq!(Regex::new("a").unwrap().replace_all("banana", "o"), "bonono");
+    }
+
+    #[test]
+    fn alternation_inside_groups() {
+        assert_eq!(find("(cat|dog)s", "hot dogs"), Some((4, 8)));
+        assert_eq!(find("(cat|dog)s", "cat dog"), None);
+        assert_eq!(find("^(ab|cd)+$", "abcdab"), Some((0, 6)));
+        assert_eq!(find("^(ab|cd)+$", "abce"), None);
+        assert_eq!(find("x(a|b(c|d))y", "xbdy"), Some((0, 4)));
+    }
+
+    #[test]
+    fn syntax_errors_say_where() {
+        let error = |pattern: &str| {
+            let error = Regex::new(pattern).unwrap_err();
+            (error.message(), error.position())
+        };
+        assert_eq!(error("abc)"), ("Unmatched ')'", 3));
+        assert_eq!(error("a(b"), ("Unterminated group", 3));
+        assert_eq!(error("[b-a]"), ("Range out of order in character class", 3));
+        assert_eq!(error("a{2,1}"), ("Quantifier minimum is larger than its maximum", 1));
+        assert_eq!(error("x|(?P<1x>a)"), ("Invalid group name", 8));
+        assert_eq!(Regex::new("abc)").unwrap_err().to_string(), "Unmatched ')' at position 3");
+    }
+
+    #[test]
+    fn empty_pattern() {
+        assert_eq!(find("", "anything"), Some((0, 0)));
+        assert_eq!(find("", ""), Some((0, 0)));
+        assert_eq!(find("^$", ""), Some((0, 0)));
+        assert_eq!(find("^$", "x"), None);
+    }
+
+    #[test]
+    fn repeated_groups_keep_the_last_capture() {
+        let regex = Regex::new("(ab){2,3}").unwrap();
+        let captures = regex.captures("xabababx").unwrap();
+        assert_eq!(captures.get(0), Some((1, 7)));
+        assert_eq!(captures.get(1), Some((5, 7)));
+
+        let captures = Regex::new(r"(\d{2}){3}").unwrap().captures("123456").unwrap();
+        assert_eq!(captures.get_str(1), Some("56"));
+
+        // A repetition given back on backtracking doesn't leave its capture behind
+        let captures = Regex::new("(a|b)+bc").unwrap().captures("abbc").unwrap();
+        assert_eq!(captures.get_str(0), Some("abbc"));
+        assert_eq!(captures.get_str(1), Some("b"));
+        let captures = Regex::new("(?:(x)|y)+z").unwrap().captures("xyz").unwrap();
+        assert_eq!(captures.get_str(1), Some("x"));
+    }
+
+    #[test]
+    fn anchors_in_alternatives() {
+        let regex = Regex::new("^a|b$").unwrap();
+        assert!(regex.is_match("abc"));
+        assert!(regex.is_match("cab"));
+        assert!(!regex.is_match("cabc"));
+        assert_eq!(find("(^foo|bar$)", "foo bar"), Some((0, 3)));
+        assert_eq!(find("(^foo|bar$)", "a foo bar"), Some((6, 9)));
+        assert_eq!(find("x(^foo|bar$)", "xfoo"), None);
+    }
+
+    #[test]
+    fn matches_at_keeps_the_text_before_it() {
+        let regex = Regex::new("[a-z]+").unwrap();
+        let text = "ab cd";
+        assert_eq!(regex.matches_at(text, 0), Some(2));
+        assert_eq!(regex.matches_at(text, 1), Some(2));
+        assert_eq!(regex.matches_at(text, 2), None);
+        assert_eq!(regex.matches_at(text, 3), Some(5));
+        assert_eq!(regex.matches_at(text, 5), None);
+        // ^ is still the start of the text, not the offset, and \b sees what's before
+        assert_eq!(Regex::new("^b").unwrap().matches_at("ab", 1), None);
+        assert_eq!(Regex::new(r"\bb").unwrap().matches_at("ab", 1), None);
+        assert_eq!(Regex::new(r"\bb").unwrap().matches_at("a b", 2), Some(3));
+    }
+
+    #[test]
+    fn class_escapes_inside_groups() {
+        assert_eq!(find(r"[\d\s]+", "ab1 2cd"), Some((2, 5)));
+        assert_eq!(find(r"[^\d]+", "12ab34"), Some((2, 4)));
+        assert_eq!(find(r"[\w-]+", "  foo-bar_1  "), Some((2, 11)));
+        assert_eq!(find(r"[\W]", "ab!"), Some((2, 3)));
+        assert_eq!(find(r"[^\s\d]", " 1 x"), Some((3, 4)));
+    }
+
+    #[test]
+    fn plus_takes_the_final_character() {
+        assert_eq!(find("a+$", "aaa"), Some((0, 3)));
+        assert_eq!(find("[a]+", "baaa"), Some((1, 4)));
+        assert_eq!(find("^a+$", "a"), Some((0, 1)));
+        assert_eq!(find(r"\d+", "x9"), Some((1, 2)));
+    }
+
+    #[test]
+    fn counted_quantifier_without_minimum() {
+        assert_eq!(find("^a{,2}$", ""), Some((0, 0)));
+        assert_eq!(find("^a{,2}$", "aa"), Some((0, 2)));
+        assert_eq!(find("^a{,2}$", "aaa"), None);
+        assert_eq!(find("ba{,2}", "baaa"), Some((0, 3)));
+        let error = Regex::new("a{3,1}").unwrap_err();
+        assert_eq!((error.message(), error.position()), ("Quantifier minimum is larger than its maximum", 1));
+    }
+
+    #[test]
+    fn negated_groups_with_ranges() {
+        assert!(Regex::new("[^a-z]").unwrap().is_match("A"));
+        assert!(Regex::new("[^a-z]").unwrap().is_match("5"));
+        assert!(Regex::new("[^a-z]").unwrap().is_match(" "));
+        assert!(!Regex::new("[^a-z]").unwrap().is_match("m"));
+        assert_eq!(find("[^a-z0-9_]", "ab9_!z"), Some((4, 5)));
+    }
+
+    #[test]
+    fn word_start_and_end() {
+        assert!(Regex::new(r"\<cat").unwrap().is_match("cat"));
+        assert!(!Regex::new(r"\<cat").unwrap().is_match("bobcat"));
+        assert!(Regex::new(r"cat\>").unwrap().is_match("bobcat"));
+        assert!(!Regex::new(r"cat\>").unwrap().is_match("cats"));
+        assert_eq!(find(r"\<cat\>", "cats bobcat cat"), Some((12, 15)));
+        // Unlike \b, the direction matters
+        assert!(!Regex::new(r"a\<").unwrap().is_match("a b"));
+    }
+
+    #[test]
+    fn split_on_matches() {
+        let comma = Regex::new(r"\s*,\s*").unwrap();
+        assert_eq!(comma.split("a, b ,c"), vec!["a", "b", "c"]);
+        assert_eq!(comma.split(",a,,b,"), vec!["", "a", "", "b", ""]);
+        assert_eq!(comma.split("abc"), vec!["abc"]);
+    }
+
+    #[test]
+    fn multibyte_ranges() {
+        let greek = Regex::new("^[α-ω]+$").unwrap();
+        assert!(greek.is_match("λογος"));
+        assert!(!greek.is_match("logos"));
+        assert_eq!(find("[α-ω]", "abγ"), Some((2, 4)));
+        assert_eq!(Regex::new("[z-a]").unwrap_err().message(), "Range out of order in character class");
+    }
+
+    #[test]
+    fn possessive_quantifiers() {
+        assert_eq!(find("a+a", "aaa"), Some((0, 3)));
+        assert_eq!(find("a++a", "aaa"), None);
+        assert_eq!(find("a*+a", "aaa"), None);
+        assert_eq!(find("a?+a", "a"), None);
+        assert_eq!(find("a++b", "aab"), Some((0, 3)));
+    }
+
+    #[test]
+    fn dot_matches_newline_only_when_asked() {
+        assert!(!Regex::new("a.b").unwrap().is_match("a\nb"));
+        let dotall = MatchOptions { dot_matches_newline: true, ..MatchOptions::default() };
+        assert!(Regex::with_options("a.b", dotall).unwrap().is_match("a\nb"));
+    }
+
+    #[test]
+    fn alternation_binds_loosest() {
+        let animals = Regex::new("cat|dog|bird").unwrap();
+        assert!(animals.is_match("a cat"));
+        assert!(animals.is_match("hotdog"));
+        assert!(animals.is_match("birdie"));
+        assert!(!animals.is_match("cow"));
+
+        // ab|cd is (ab)|(cd), not a(b|c)d
+        assert_eq!(find("ab|cd", "xcd"), Some((1, 3)));
+        assert_eq!(find("ab|cd", "acd"), Some((1, 3)));
+        assert_eq!(find("ab|cd", "abd"), Some((0, 2)));
+    }
+
+    #[test]
+    fn search_lines_yields_matching_lines() {
+        let regex = Regex::new("a+").unwrap();
+        let found: Vec<_> = regex.search_lines(&b"apple\nberry\nbanana\n"[..]).map(Result::unwrap).collect();
+        assert_eq!(found, vec![(1, "apple".to_string()), (3, "banana".to_string())]);
+
+        // Invalid UTF-8 is an error for that line only
+        let mut lines = regex.search_lines(&b"\xff\nbanana\n"[..]);
+        assert!(lines.next().unwrap().is_err());
+        assert_eq!(lines.next().unwrap().unwrap(), (2, "banana".to_string()));
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn negated_posix_classes() {
+        let not_alpha = Regex::new("[[:^alpha:]]").unwrap();
+        assert!(not_alpha.is_match("5"));
+        assert!(not_alpha.is_match("!"));
+        assert!(!not_alpha.is_match("abc"));
+        assert_eq!(find("[[:^space:]x]+", "  ab x"), Some((2, 4)));
+    }
+
+    #[test]
+    fn unicode_case_folding() {
+        let ascii = MatchOptions { ignore_case: true, ..MatchOptions::default() };
+        let unicode = MatchOptions { ignore_case: true, unicode: true, ..MatchOptions::default() };
+        assert!(!Regex::with_options("ärger", ascii.clone()).unwrap().is_match("ÄRGER"));
+        assert!(Regex::with_options("ärger", unicode.clone()).unwrap().is_match("ÄRGER"));
+        assert!(Regex::with_options("STRASSE", unicode.clone()).unwrap().is_match("Strasse"));
+        assert!(Regex::with_options("[à-ÿ]", unicode.clone()).unwrap().is_match("É"));
+        // Only simple folding, so ß doesn't expand to ss
+        assert!(!Regex::with_options("STRASSE", unicode).unwrap().is_match("straße"));
+    }
+
+    #[test]
+    fn literal_fast_path_agrees_with_the_engine() {
+        // A class around the first character stops the pattern counting as literal
+        let pairs = [("abc", "[a]bc"), ("^abc", "^[a]bc"), ("abc$", "[a]bc$"), ("^abc$", "^[a]bc$")];
+        let texts = ["abc", "xabc", "abcx", "xabcx", "ab", "", "abc\n", "aabcabc"];
+        for (literal, general) in pairs {
+            let (literal, general) = (Regex::new(literal).unwrap(), Regex::new(general).unwrap());
+            for text in texts {
+                assert_eq!(literal.is_match(text), general.is_match(text), "{:?} on {:?}", literal, text);
+            }
+        }
+    }
+
+    #[test]
+    fn lazy_optional() {
+        assert_eq!(find("a?a", "aa"), Some((0, 2)));
+        assert_eq!(find("a??a", "aa"), Some((0, 1)));
+        assert_eq!(find("(a??)a", "aa"), Some((0, 1)));
+        assert_eq!(find("a??b", "ab"), Some((0, 2)));
+    }
+
+    #[test]
+    fn basic_syntax() {
+        let basic = MatchOptions { basic_syntax: true, ..MatchOptions::default() };
+        let basic = |pattern: &str| Regex::with_options(pattern, basic.clone()).unwrap();
+        assert!(basic("a+").is_match("a+"));
+        assert!(!basic("a+").is_match("aa"));
+        assert!(basic(r"a\+").is_match("aa"));
+        assert!(basic(r"\(ab\)\{2\}").is_match("abab"));
+        assert!(basic("a|b").is_match("a|b"));
+        assert!(!basic("a|b").is_match("a"));
+        assert!(basic(r"a\|b").is_match("b"));
+    }
+
+    #[test]
+    fn empty_matches_move_on() {
+        let spans: Vec<_> = Regex::new("a*").unwrap().find_iter("bbb").map(|m| (m.start, m.end)).collect();
+        assert_eq!(spans, [(0, 0), (1, 1), (2, 2), (3, 3)]);
+        let spans: Vec<_> = Regex::new("(foo)?").unwrap().find_iter("xfoo").map(|m| (m.start, m.end)).collect();
+        assert_eq!(spans, [(0, 0), (1, 4)]);
+    }
+
+    #[test]
+    fn control_character_escapes() {
+        assert!(Regex::new(r"a\tb").unwrap().is_match("a\tb"));
+        assert!(!Regex::new(r"a\tb").unwrap().is_match("atb"));
+        assert_eq!(find(r"\t[^\t]*\t", "id\tname\tage"), Some((2, 8)));
+        assert_eq!(find(r"[\t ]+", "a \t b"), Some((1, 4)));
+        assert!(Regex::new(r"\r\n").unwrap().is_match("a\r\nb"));
+        assert!(Regex::new(r"\f\v\0").unwrap().is_match("\x0c\x0b\0"));
+    }
+
+    #[test]
+    fn hex_and_unicode_escapes() {
+        assert!(Regex::new(r"\x41").unwrap().is_match("A"));
+        assert_eq!(find(r"a\x20b", "xa b"), Some((1, 4)));
+        assert!(Regex::new(r"\u{00e9}").unwrap().is_match("café"));
+        assert!(Regex::new(r"\u{1F600}").unwrap().is_match("hi 😀"));
+        assert!(Regex::new(r"[\x41-\x43]").unwrap().is_match("B"));
+        assert!(Regex::new(r"\xZZ").is_err());
+        assert!(Regex::new(r"\x4").is_err());
+        assert!(Regex::new(r"\u{110000}").is_err());
+        assert!(Regex::new(r"\u{D800}").is_err());
+        assert!(Regex::new(r"\u{41").is_err());
+    }
+
+    #[test]
+    fn named_groups() {
+        let date = Regex::new(r"(?<year>\d{4})-(?P<month>\d{2})").unwrap();
+        let captures = date.captures("on 2024-03-15").unwrap();
+        assert_eq!(captures.get_by_name("year"), Some((3, 7)));
+        assert_eq!(captures.get_by_name("month"), Some((8, 10)));
+        assert_eq!(captures.get_by_name("day"), None);
+        // Named groups are numbered along with the others
+        assert_eq!(captures.get_str(2), Some("03"));
+        assert_eq!(date.replace_all("2024-03", "${month}/${year}"), "03/2024");
+        assert_eq!(Regex::new("(?<1a>x)").unwrap_err().message(), "Invalid group name");
+    }
+
+    #[test]
+    fn non_capturing_groups() {
+        let regex = Regex::new(r"(?:ab)(cd)\1").unwrap();
+        assert!(regex.is_match("abcdcd"));
+        assert!(!regex.is_match("abcdab"));
+        assert_eq!(regex.captures("abcdcd").unwrap().get_str(1), Some("cd"));
+        assert_eq!(find("(?:ab)+", "xababx"), Some((1, 5)));
+    }
+
+    #[test]
+    fn absolute_anchors() {
+        assert!(Regex::new(r"\Afoo").unwrap().is_match("foo\nbar"));
+        assert!(!Regex::new(r"\Afoo").unwrap().is_match("bar\nfoo"));
+        assert!(Regex::new(r"bar\z").unwrap().is_match("foo\nbar"));
+        assert!(!Regex::new(r"bar\z").unwrap().is_match("bar\nfoo"));
+        assert_eq!(find(r"\Aa", "aa"), Some((0, 1)));
+    }
+
+    #[test]
+    fn empty_repetition_ends_the_loop() {
+        // The empty alternative comes first, so it wins
+        assert_eq!(find("(|a)?", "a"), Some((0, 0)));
+        assert_eq!(find("(|a)*", "a"), Some((0, 0)));
+        let captures = Regex::new("(a*)?x").unwrap().captures("x").unwrap();
+        assert_eq!(captures.get(1), Some((0, 0)));
+        assert_eq!(find("(a*)+b", "aab"), Some((0, 3)));
+    }
+
+    #[test]
+    fn nested_repetitions_fail_quickly() {
+        // Each of these takes exponential time without remembering failures
+        // inside the repeated group
+        let text = "a".repeat(100);
+        assert!(!Regex::new("(a+)+b").unwrap().is_match(&text));
+        assert!(!Regex::new("(a|aa)*b").unwrap().is_match(&text));
+        assert!(!Regex::new(r"^(\w+\s?)*$").unwrap().is_match(&format!("{}!", text)));
+        assert_eq!(find("(a+)+b", &format!("{}b", text)), Some((0, 101)));
+        assert_eq!(find("(a|aa)*b", &format!("{}b", text)), Some((0, 101)));
+    }
+
+    #[test]
+    fn memo_tells_what_follows_a_group_apart() {
+        // The same group body can be followed by different repetitions and tokens
+        assert_eq!(find("(a|b){3}c", "ababc"), Some((1, 5)));
+        assert_eq!(find("(?:a+){2}b", "aab"), Some((0, 3)));
+        assert_eq!(find("(?:a+){2}b", "ab"), None);
+        assert_eq!(find("(a|ab)(c|bcd)(d*)", "abcd"), Some((0, 4)));
+        assert_eq!(find("((a|b)+c)+d", "abcbcacd"), Some((0, 8)));
+        assert_eq!(find("(?:a*b)*+c|x(a+)+y", "xaaay"), Some((0, 5)));
+    }
+
+    #[test]
+    fn backreferences_to_missing_groups_are_errors() {
+        let error = |pattern: &str| {
+            let error = Regex::new(pattern).unwrap_err();
+            (error.message(), error.position())
+        };
+        assert_eq!(error(r"(a)\2"), ("Invalid back reference", 4));
+        assert_eq!(error(r"a\1"), ("Invalid back reference", 2));
+        assert_eq!(error(r"x|(?:a)\1"), ("Invalid back reference", 8));
+        let basic = MatchOptions { basic_syntax: true, ..MatchOptions::default() };
+        assert_eq!(Regex::with_options(r"\(a\)\2", basic).unwrap_err().position(), 6);
+        // Groups in any alternative count
+        assert!(Regex::new(r"\1|(a)").is_ok());
+        assert!(Regex::new(r"(a)(b)\2").is_ok());
+    }
+
+    #[test]
+    fn absolute_anchors_differ_from_line_anchors() {
+        let multi_line = MatchOptions { newline_is_ordinary: true, multi_line: true, ..MatchOptions::default() };
+        let regex = |pattern: &str| Regex::with_options(pattern, multi_line.clone()).unwrap();
+        assert!(regex("^foo").is_match("bar\nfoo"));
+        assert!(!regex(r"\Afoo").is_match("bar\nfoo"));
+        assert!(regex("bar$").is_match("bar\nfoo"));
+        assert!(!regex(r"bar\z").is_match("bar\nfoo"));
+        assert_eq!(regex("^b").find("ab\nbc").map(|m| (m.start, m.end)), Some((3, 4)));
+
+        // Without multi_line, ^ and $ only match at the ends too
+        let ordinary = MatchOptions { newline_is_ordinary: true, ..MatchOptions::default() };
+        assert!(!Regex::with_options("^foo", ordinary).unwrap().is_match("bar\nfoo"));
+
+        // A trailing newline is ignored by $ but not by \z
+        assert!(Regex::new("bar$").unwrap().is_match("bar\n"));
+        assert!(!Regex::new(r"bar\z").unwrap().is_match("bar\n"));
+        assert!(Regex::new(r"bar\z").unwrap().is_match("bar"));
+    }
+}
@@ -1,212 +1,1068 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::env;
-use std::io;
+use std::fs::{self, File};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Instant;
 
-#[derive(Debug, Clone)]
-enum PatternToken {
-    Digit,          // \d
-    Word,           // \w
-    Char(char),     // literal character
-    CharGroup(Vec<char>, bool),  // [...] or [^...]
-    Plus(Box<PatternToken>),     // token+
-    Question(Box<PatternToken>), // token?
-}
-
-fn tokenize_pattern(pattern: &str) -> Vec<PatternToken> {
-    let mut tokens = Vec::new();
-    let mut chars = pattern.chars().peekable();
-    
-    while let Some(c) = chars.next() {
-        let token = match c {
-            '\\' => {
-                if let Some(special) = chars.next() {
-                    match special {
-                        'd' => PatternToken::Digit,
-                        'w' => PatternToken::Word,
-                        _ => PatternToken::Char(special),
-                    }
-                } else {
-                    continue;
-                }
+use codecrafters_grep::{Match, MatchOptions, Regex};
+
+// ANSI escapes wrapped around matched text when coloring output
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+// Wrap each match in the line in color escapes
+fn highlight(line: &str, matches: &[Match]) -> String {
+    let mut highlighted = String::new();
+    let mut last_end = 0;
+
+    for found in matches {
+        highlighted.push_str(&line[last_end..found.start]);
+        highlighted.push_str(MATCH_COLOR);
+        highlighted.push_str(&line[found.start..found.end]);
+        highlighted.push_str(COLOR_RESET);
+        last_end = found.end;
+    }
+    highlighted.push_str(&line[last_end..]);
+    highlighted
+}
+
+// A whole line as printed: cut to --max-columns characters (never in the
+// middle of one) with an ellipsis marking the cut, and with its matches
+// highlighted when coloring
+fn display_line<'a>(line: &'a str, matches: &[Match], config: &Config) -> Cow<'a, str> {
+    let (shown, cut) = match config.max_columns.and_then(|max| line.char_indices().nth(max)) {
+        Some((end, _)) => (&line[..end], true),
+        None => (line, false),
+    };
+    let mut displayed = if config.color {
+        // Matches running past the cut are only highlighted up to it
+        let visible: Vec<Match> = matches
+            .iter()
+            .filter(|found| found.start < shown.len())
+            .map(|found| Match { start: found.start, end: found.end.min(shown.len()) })
+            .collect();
+        Cow::Owned(highlight(shown, &visible))
+    } else {
+        Cow::Borrowed(shown)
+    };
+    if cut {
+        displayed.to_mut().push('…');
+    }
+    displayed
+}
+
+// One or more patterns, where a line matches if any of them do
+struct Matcher {
+    regexes: Vec<Regex>,
+}
+
+impl Matcher {
+    fn new(patterns: &[String], options: &MatchOptions) -> Result<Matcher, String> {
+        let regexes = patterns
+            .iter()
+            .map(|pattern| Regex::with_options(pattern, options.clone()).map_err(|e| e.to_string()))
+            .collect::<Result<_, _>>()?;
+        Ok(Matcher { regexes })
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        self.regexes.iter().any(|regex| regex.is_match(line))
+    }
+
+    fn is_single_char(&self) -> bool {
+        self.regexes.iter().all(Regex::is_single_char)
+    }
+
+    fn matches_char(&self, c: char) -> bool {
+        self.regexes.iter().any(|regex| regex.matches_char(c))
+    }
+
+    // Every non-empty match of any pattern, left to right. Where matches
+    // overlap, the one starting first (or the longest, if tied) wins.
+    fn find_all(&self, line: &str) -> Vec<Match> {
+        let mut candidates: Vec<Match> = self.regexes
+            .iter()
+            .flat_map(|regex| regex.find_iter(line))
+            .filter(|found| found.end > found.start)
+            .collect();
+        candidates.sort_by_key(|found| (found.start, usize::MAX - found.end));
+
+        let mut matches: Vec<Match> = Vec::new();
+        for found in candidates {
+            if matches.last().map_or(true, |last| found.start >= last.end) {
+                matches.push(found);
+            }
+        }
+        matches
+    }
+
+    // What capture group `group` matched in each of the matches find_all would
+    // give, leaving out those where the group didn't take part or matched nothing
+    fn find_group(&self, line: &str, group: usize) -> Vec<Match> {
+        let mut candidates: Vec<(Match, Option<(usize, usize)>)> = self.regexes
+            .iter()
+            .flat_map(|regex| regex.captures_iter(line))
+            .filter_map(|captures| {
+                let (start, end) = captures.get(0)?;
+                (end > start).then(|| (Match { start, end }, captures.get(group)))
+            })
+            .collect();
+        candidates.sort_by_key(|(found, _)| (found.start, usize::MAX - found.end));
+
+        let mut last_end = 0;
+        let mut spans = Vec::new();
+        for (found, span) in candidates {
+            if found.start >= last_end {
+                last_end = found.end;
+                spans.extend(span.filter(|(start, end)| end > start).map(|(start, end)| Match { start, end }));
+            }
+        }
+        spans
+    }
+}
+
+// Write a line to the output, prefixed with its file name, line number and
+// byte offset when given. Each part of the prefix is followed by `separator`,
+// which is ':' for selected lines (as `file:lineno:offset:line`) and '-' for
+// context lines. The line ends with `terminator`, which is '\0' with -z.
+fn print_line(out: &mut impl Write, filename: Option<&str>, line_number: Option<usize>, byte_offset: Option<usize>, separator: char, line: &str, terminator: char) -> Result<(), &'static str> {
+    let mut prefix = String::new();
+    if let Some(filename) = filename {
+        prefix.push_str(filename);
+        prefix.push(separator);
+    }
+    if let Some(line_number) = line_number {
+        prefix.push_str(&line_number.to_string());
+        prefix.push(separator);
+    }
+    if let Some(byte_offset) = byte_offset {
+        prefix.push_str(&byte_offset.to_string());
+        prefix.push(separator);
+    }
+    write!(out, "{}{}{}", prefix, line, terminator).map_err(|_| "Failed to write output")
+}
+
+// Output that's flushed after every line, for --line-buffered. Each line is
+// printed with a single write!, so flushing after each of those is enough.
+struct LineBuffered<W>(W);
+
+impl<W: Write> Write for LineBuffered<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments) -> io::Result<()> {
+        self.0.write_fmt(args)?;
+        self.0.flush()
+    }
+}
+
+// Quote `text` as a JSON string
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Write a selected line as a JSON object on its own line, for --json
+fn print_json(out: &mut impl Write, filename: Option<&str>, line_number: usize, line: &str, matches: &[Match]) -> Result<(), &'static str> {
+    let file = filename.map_or_else(|| "null".to_string(), json_string);
+    let matches: Vec<String> = matches
+        .iter()
+        .map(|found| format!("{{\"start\":{},\"end\":{}}}", found.start, found.end))
+        .collect();
+    writeln!(out, "{{\"file\":{},\"line_number\":{},\"line\":{},\"matches\":[{}]}}",
+        file, line_number, json_string(line), matches.join(","))
+        .map_err(|_| "Failed to write output")
+}
+
+// Options collected from the command line
+struct Config {
+    patterns: Vec<String>,
+    pattern_files: Vec<String>,
+    match_options: MatchOptions,
+    paths: Vec<String>,
+    line_number: bool,
+    byte_offset: bool,
+    null_data: bool,
+    text: bool,
+    slurp: bool,
+    count: bool,
+    count_matches: bool,
+    total: bool,
+    invert_match: bool,
+    only_matching: bool,
+    // The capture group printed by -o, where 0 is the whole match
+    only_matching_group: usize,
+    json: bool,
+    list_files: Option<ListFiles>,
+    // Whether -H or -h forced file names on or off
+    with_filename: Option<bool>,
+    quiet: bool,
+    max_count: Option<usize>,
+    before_context: usize,
+    after_context: usize,
+    recursive: bool,
+    debug: bool,
+    includes: Vec<String>,
+    excludes: Vec<String>,
+    color: bool,
+    // How many characters of a line to print before cutting it off
+    max_columns: Option<usize>,
+    // Printed between groups of context lines, unless --no-group-separator
+    group_separator: Option<String>,
+    encoding: Encoding,
+    line_buffered: bool,
+    timing: bool,
+}
+
+// Which file names to print instead of lines, as given by -l or -L
+#[derive(Clone, Copy)]
+enum ListFiles {
+    WithMatches,
+    WithoutMatch,
+}
+
+// How the bytes of the input are read as text, as given by --encoding
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Utf8,
+    // Each byte is the character with that code point
+    Latin1,
+}
+
+impl Encoding {
+    // Bytes that aren't valid UTF-8 become U+FFFD, like grep searching on past them
+    fn decode(self, offset: usize, bytes: Vec<u8>) -> Line {
+        match self {
+            Encoding::Utf8 => match String::from_utf8(bytes) {
+                Ok(text) => Line { offset, text, invalid: None },
+                Err(e) => {
+                    let text = String::from_utf8_lossy(e.as_bytes()).into_owned();
+                    Line { offset, text, invalid: Some(e.into_bytes()) }
+                },
             },
-            '[' => {
-                let is_negative = chars.peek() == Some(&'^');
-                if is_negative {
-                    chars.next();  // consume '^'
-                }
-                let mut group_chars = Vec::new();
-                while let Some(gc) = chars.next() {
-                    if gc == ']' {
+            Encoding::Latin1 => Line { offset, text: bytes.into_iter().map(char::from).collect(), invalid: None },
+        }
+    }
+
+    // How many bytes of input were decoded to `text`, the start of a line read
+    // from `invalid` if that wasn't valid UTF-8. That differs from its length
+    // in Latin-1 since characters from 0x80 up take two bytes in UTF-8, and
+    // where a U+FFFD stands for however many bytes it replaced.
+    fn input_len(self, text: &str, invalid: Option<&[u8]>) -> usize {
+        match (self, invalid) {
+            (Encoding::Utf8, None) => text.len(),
+            (Encoding::Utf8, Some(bytes)) => {
+                // Step over the valid runs and the replaced bytes after each
+                let (mut text_len, mut input_len) = (0, 0);
+                for chunk in bytes.utf8_chunks() {
+                    let valid = chunk.valid().len();
+                    if text.len() <= text_len + valid {
                         break;
                     }
-                    group_chars.push(gc);
+                    text_len += valid + char::REPLACEMENT_CHARACTER.len_utf8();
+                    input_len += valid + chunk.invalid().len();
                 }
-                PatternToken::CharGroup(group_chars, is_negative)
+                input_len + text.len() - text_len
             },
-            _ => PatternToken::Char(c),
-        };
-        
-        // Check for quantifier after the token
-        if chars.peek() == Some(&'+') {
-            chars.next(); // consume '+'
-            tokens.push(PatternToken::Plus(Box::new(token)));
-        } else if chars.peek() == Some(&'?') {
-            chars.next(); // consume '?'
-            tokens.push(PatternToken::Question(Box::new(token)));
-        } else {
-            tokens.push(token);
+            (Encoding::Latin1, _) => text.chars().count(),
         }
     }
-    tokens
 }
 
-fn matches_token(c: char, token: &PatternToken) -> bool {
-    match token {
-        PatternToken::Digit => c.is_ascii_digit(),
-        PatternToken::Word => c.is_ascii_alphanumeric() || c == '_',
-        PatternToken::Char(pattern_char) => &c == pattern_char,
-        PatternToken::CharGroup(chars, is_negative) => {
-            let contains = chars.contains(&c);
-            if *is_negative { !contains } else { contains }
+// A line of input decoded as text
+struct Line {
+    // Where it starts in the input
+    offset: usize,
+    text: String,
+    // The bytes it was decoded from when they weren't valid UTF-8, since the
+    // text alone doesn't say how many of them each U+FFFD replaced
+    invalid: Option<Vec<u8>>,
+}
+
+// When to highlight matches, as given by --color[=when]
+enum ColorChoice {
+    Always,
+    Never,
+    Auto,
+}
+
+// Read the number following a flag like -m
+fn next_number(args: &mut impl Iterator<Item = String>, error: &'static str) -> Result<usize, &'static str> {
+    args.next().ok_or(error)?.parse().map_err(|_| error)
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
+    let mut patterns = Vec::new();
+    let mut pattern_files = Vec::new();
+    let mut match_options = MatchOptions::default();
+    let mut paths = Vec::new();
+    let mut line_number = false;
+    let mut byte_offset = false;
+    let mut null_data = false;
+    let mut text = false;
+    let mut slurp = false;
+    let mut count = false;
+    let mut count_matches = false;
+    let mut total = false;
+    let mut invert_match = false;
+    let mut only_matching = false;
+    let mut only_matching_group = 0;
+    let mut json = false;
+    let mut list_files = None;
+    let mut with_filename = None;
+    let mut quiet = false;
+    let mut max_count = None;
+    let mut before_context = 0;
+    let mut after_context = 0;
+    let mut recursive = false;
+    let mut debug = false;
+    let mut includes = Vec::new();
+    let mut excludes = Vec::new();
+    let mut color_choice = ColorChoice::Never;
+    let mut max_columns = None;
+    let mut group_separator = Some(String::from("--"));
+    let mut encoding = Encoding::Utf8;
+    let mut line_buffered = false;
+    let mut timing = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Only the last of -E and -G counts
+            "-E" | "--extended-regexp" => match_options.basic_syntax = false,
+            "-G" | "--basic-regexp" => match_options.basic_syntax = true,
+            "-e" | "--regexp" => patterns.push(args.next().ok_or("Expected a pattern after '-e'")?),
+            "-f" | "--file" => pattern_files.push(args.next().ok_or("Expected a file after '-f'")?),
+            "-i" | "--ignore-case" => match_options.ignore_case = true,
+            "-w" | "--word-regexp" => match_options.word_regexp = true,
+            "-x" | "--line-regexp" => match_options.line_regexp = true,
+            "--unicode" => match_options.unicode = true,
+            _ if arg.starts_with("--word-chars=") => match_options.word_chars = arg["--word-chars=".len()..].to_string(),
+            "-F" | "--fixed-strings" => match_options.fixed_string = true,
+            "-n" | "--line-number" => line_number = true,
+            "-b" | "--byte-offset" => byte_offset = true,
+            "-z" | "--null-data" | "--null" => {
+                // Records may contain newlines, which are then just ordinary characters
+                null_data = true;
+                match_options.newline_is_ordinary = true;
+                match_options.dot_matches_newline = true;
+            },
+            "-a" | "--text" => text = true,
+            _ if arg.starts_with("--encoding=") => {
+                encoding = match &arg["--encoding=".len()..] {
+                    "utf8" | "utf-8" => Encoding::Utf8,
+                    "latin1" | "latin-1" | "iso-8859-1" => Encoding::Latin1,
+                    _ => return Err("Expected 'utf8' or 'latin1' for --encoding"),
+                };
+            },
+            "--slurp" => {
+                // The whole input is one line, so patterns can match across
+                // newlines, though ^ and $ still match at the lines within it
+                slurp = true;
+                match_options.newline_is_ordinary = true;
+                match_options.multi_line = true;
+            },
+            // Not -s, which grep already uses for --no-messages
+            "--dotall" => match_options.dot_matches_newline = true,
+            "-c" | "--count" => count = true,
+            "--total" => total = true,
+            "--count-matches" => {
+                count = true;
+                count_matches = true;
+            },
+            "-v" | "--invert-match" => invert_match = true,
+            "-o" | "--only-matching" => only_matching = true,
+            // -oN or --only-matching=N prints just capture group N
+            _ if arg.starts_with("--only-matching=") || (arg.starts_with("-o") && arg.len() > 2) => {
+                let group = arg.trim_start_matches("--only-matching=").trim_start_matches("-o");
+                only_matching = true;
+                only_matching_group = group.parse().map_err(|_| "Expected a group number for --only-matching")?;
+            },
+            "--json" => json = true,
+            "-l" | "--files-with-matches" => list_files = Some(ListFiles::WithMatches),
+            "-L" | "--files-without-match" => list_files = Some(ListFiles::WithoutMatch),
+            "-H" | "--with-filename" => with_filename = Some(true),
+            "-h" | "--no-filename" => with_filename = Some(false),
+            "-q" | "--quiet" => quiet = true,
+            "-m" | "--max-count" => max_count = Some(next_number(&mut args, "Expected a number after '-m'")?),
+            "-A" | "--after-context" => after_context = next_number(&mut args, "Expected a number after '-A'")?,
+            "-B" | "--before-context" => before_context = next_number(&mut args, "Expected a number after '-B'")?,
+            "-C" | "--context" => {
+                let lines = next_number(&mut args, "Expected a number after '-C'")?;
+                before_context = lines;
+                after_context = lines;
+            },
+            "--max-columns" => max_columns = Some(next_number(&mut args, "Expected a number after '--max-columns'")?),
+            _ if arg.starts_with("--group-separator=") => group_separator = Some(arg["--group-separator=".len()..].to_string()),
+            "--no-group-separator" => group_separator = None,
+            "--line-buffered" => line_buffered = true,
+            "-r" | "--recursive" => recursive = true,
+            // Undocumented: show how the patterns were parsed
+            "--debug" => debug = true,
+            "--timing" => timing = true,
+            _ if arg.starts_with("--include=") => includes.push(arg["--include=".len()..].to_string()),
+            _ if arg.starts_with("--exclude=") => excludes.push(arg["--exclude=".len()..].to_string()),
+            "--color" => color_choice = ColorChoice::Auto,
+            _ if arg.starts_with("--color=") => {
+                color_choice = match &arg["--color=".len()..] {
+                    "always" => ColorChoice::Always,
+                    "never" => ColorChoice::Never,
+                    "auto" => ColorChoice::Auto,
+                    _ => return Err("Expected 'always', 'never' or 'auto' for --color"),
+                };
+            },
+            _ if arg.starts_with('-') => return Err("Unrecognized argument"),
+            _ => paths.push(arg),
         }
-        PatternToken::Plus(_) => false, // This should not be called directly
-        PatternToken::Question(_) => false, // This should not be called directly
     }
-}
 
-fn match_tokens_at_position(input_chars: &[char], tokens: &[PatternToken], start_pos: usize) -> Option<usize> {
-    fn backtrack_match(input_chars: &[char], tokens: &[PatternToken], pos: usize, token_idx: usize) -> Option<usize> {
-        if token_idx >= tokens.len() {
-            return Some(pos);
+    // -o prints parts of matching lines, which makes no sense along with these
+    if only_matching {
+        if invert_match {
+            return Err("-o can't be used with -v");
+        }
+        // --count-matches counts by way of -c, but wasn't given as -c
+        if count_matches {
+            return Err("-o can't be used with --count-matches");
         }
-        
-        match &tokens[token_idx] {
-            PatternToken::Plus(inner_token) => {
-                // Must match at least once
-                if pos >= input_chars.len() || !matches_token(input_chars[pos], inner_token) {
-                    return None;
+        if count {
+            return Err("-o can't be used with -c");
+        }
+        if list_files.is_some() {
+            return Err("-o can't be used with -l or -L");
+        }
+    }
+
+    // Without -e or -f the first argument that isn't an option is the pattern
+    if patterns.is_empty() && pattern_files.is_empty() {
+        if paths.is_empty() {
+            return Err("Usage: program [OPTION...] [-E] <pattern> [file...]");
+        }
+        patterns.push(paths.remove(0));
+    }
+
+    Ok(Config {
+        patterns,
+        pattern_files,
+        match_options,
+        paths,
+        line_number,
+        byte_offset,
+        null_data,
+        text,
+        slurp,
+        count,
+        count_matches,
+        total,
+        invert_match,
+        only_matching,
+        only_matching_group,
+        json,
+        list_files,
+        with_filename,
+        quiet,
+        max_count,
+        before_context,
+        after_context,
+        recursive,
+        debug,
+        includes,
+        excludes,
+        // NO_COLOR turns highlighting off regardless of --color
+        color: env::var_os("NO_COLOR").is_none() && match color_choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stdout().is_terminal(),
+        },
+        max_columns,
+        group_separator,
+        encoding,
+        line_buffered,
+        timing,
+    })
+}
+
+// Read lines like `BufRead::lines`, along with the byte offset in the input
+// where each one starts. Lines end at `terminator`, which is '\n' except with
+// -z. Lines ending in "\r\n" lose the '\r' too, so CRLF input matches `$` the
+// same as LF input.
+fn lines_with_offsets(mut reader: impl BufRead, terminator: u8, encoding: Encoding) -> impl Iterator<Item = io::Result<Line>> {
+    let mut offset = 0;
+    iter::from_fn(move || {
+        let mut bytes = Vec::new();
+        match reader.read_until(terminator, &mut bytes) {
+            Ok(0) => None,
+            Ok(read) => {
+                let start = offset;
+                offset += read;
+                if bytes.last() == Some(&terminator) {
+                    bytes.pop();
+                    if terminator == b'\n' && bytes.last() == Some(&b'\r') {
+                        bytes.pop();
+                    }
                 }
-                
-                // Try different numbers of matches (greedy approach with backtracking)
-                let mut max_matches = 1;
-                while pos + max_matches < input_chars.len() && 
-                      matches_token(input_chars[pos + max_matches], inner_token) {
-                    max_matches += 1;
+                Some(Ok(encoding.decode(start, bytes)))
+            },
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+// Whether any character of the input matches a single-character pattern, reading
+// it a buffer at a time so a huge line never has to be held in memory. Line
+// endings (`terminator`, or a '\r' just before a '\n') aren't part of any line,
+// so they're never matched. Invalid UTF-8 reads as U+FFFD, the same as in lines.
+fn stream_match(mut reader: impl BufRead, matcher: &Matcher, terminator: char) -> Result<bool, SearchError> {
+    // Bytes of a character split across the end of the last buffer
+    let mut partial = Vec::new();
+    let mut pending_cr = false;
+
+    loop {
+        let buffer = reader.fill_buf().map_err(SearchError::Read)?;
+        if buffer.is_empty() {
+            // A character cut off by the end of the input is invalid too
+            let cut_off = !partial.is_empty() && matcher.matches_char(char::REPLACEMENT_CHARACTER);
+            return Ok(cut_off || (pending_cr && matcher.matches_char('\r')));
+        }
+        let read = buffer.len();
+        partial.extend_from_slice(buffer);
+        reader.consume(read);
+
+        // How much of `partial` has been checked
+        let mut checked = 0;
+        loop {
+            let (valid, invalid) = match std::str::from_utf8(&partial[checked..]) {
+                Ok(text) => (text.len(), None),
+                // Only an incomplete character at the very end can be finished by the next buffer
+                Err(e) => (e.valid_up_to(), e.error_len()),
+            };
+            let text = std::str::from_utf8(&partial[checked..checked + valid]).map_err(|_| "Failed to read input")?;
+            for c in text.chars().chain(invalid.map(|_| char::REPLACEMENT_CHARACTER)) {
+                if pending_cr && c != '\n' && matcher.matches_char('\r') {
+                    return Ok(true);
                 }
-                
-                // Try from maximum matches down to minimum (1)
-                for num_matches in (1..=max_matches).rev() {
-                    if let Some(final_pos) = backtrack_match(input_chars, tokens, pos + num_matches, token_idx + 1) {
-                        return Some(final_pos);
-                    }
+                pending_cr = c == '\r' && terminator == '\n';
+                if c != terminator && !pending_cr && matcher.matches_char(c) {
+                    return Ok(true);
                 }
-                None
+            }
+            checked += valid + invalid.unwrap_or(0);
+            if invalid.is_none() {
+                break;
+            }
+        }
+        partial.drain(..checked);
+    }
+}
+
+// Read all of the input as a single line starting at offset 0, for --slurp.
+// Only its final line ending is dropped. Empty input has no lines at all.
+fn read_whole(mut reader: impl BufRead, encoding: Encoding) -> Option<io::Result<Line>> {
+    let mut bytes = Vec::new();
+    if let Err(e) = reader.read_to_end(&mut bytes) {
+        return Some(Err(e));
+    }
+    if bytes.ends_with(b"\n") {
+        bytes.pop();
+        if bytes.ends_with(b"\r") {
+            bytes.pop();
+        }
+    }
+    (!bytes.is_empty()).then(|| Ok(encoding.decode(0, bytes)))
+}
+
+// Print every selected line from `reader` (or just their count with -c, or
+// the number of matches in them with --count-matches) along with the context
+// lines asked for by -A and -B, returning how many lines were selected. Lines
+// are selected when they match, or when they don't with -v.
+fn search(reader: impl BufRead, filename: Option<&str>, binary: bool, matcher: &Matcher, config: &Config, out: &mut impl Write, total_count: &mut usize) -> Result<usize, SearchError> {
+    // Groups of lines that aren't next to each other are separated by `--`,
+    // or whatever --group-separator gives
+    fn separate_group(out: &mut impl Write, config: &Config, last_printed: Option<usize>, index: usize) -> Result<(), &'static str> {
+        match &config.group_separator {
+            Some(separator) if last_printed.is_some_and(|last| index > last + 1) => {
+                writeln!(out, "{}", separator).map_err(|_| "Failed to write output")
             },
-            PatternToken::Question(inner_token) => {
-                // Can match zero or one time
-                let max_matches = if pos < input_chars.len() && matches_token(input_chars[pos], inner_token) {
-                    1
-                } else {
-                    0
+            _ => Ok(()),
+        }
+    }
+
+    let terminator = if config.null_data { '\0' } else { '\n' };
+
+    // When all that matters is whether anything matches, single-character
+    // patterns can be checked without reading whole lines at a time (as long
+    // as the input is UTF-8, which is all that check reads)
+    if (config.quiet || config.list_files.is_some()) && !config.invert_match && !config.slurp &&
+        config.max_count != Some(0) && config.encoding == Encoding::Utf8 && matcher.is_single_char()
+    {
+        return Ok(usize::from(stream_match(reader, matcher, terminator)?));
+    }
+
+    let mut selected_count = 0;
+    let mut match_count = 0;
+    let lines: Box<dyn Iterator<Item = io::Result<Line>>> = if config.slurp {
+        Box::new(read_whole(reader, config.encoding).into_iter())
+    } else {
+        Box::new(lines_with_offsets(reader, terminator as u8, config.encoding))
+    };
+    let mut lines = lines.enumerate();
+    // Context only applies when whole lines are printed
+    let context = !config.only_matching && !config.json && (config.before_context > 0 || config.after_context > 0);
+    // Recent unselected lines (with their index and offset) that may be printed before a selected one
+    let mut before: VecDeque<(usize, usize, String)> = VecDeque::with_capacity(config.before_context);
+    // How many more lines to print after the last selected one
+    let mut after_remaining = 0;
+    let mut last_printed = None;
+
+    loop {
+        // Once -m's limit of selected lines is reached, only read on for the
+        // context after the last one, selecting nothing more
+        let limit_reached = config.max_count == Some(selected_count);
+        if limit_reached && after_remaining == 0 {
+            break;
+        }
+        let Some((index, line)) = lines.next() else {
+            break;
+        };
+        let Line { offset: line_offset, text: line, invalid } = line.map_err(SearchError::Read)?;
+        if !limit_reached && matcher.is_match(&line) != config.invert_match {
+            selected_count += 1;
+            // With -q, -l or -L, the first selected line settles the file, as
+            // it does for binary input whose lines aren't printed
+            if config.quiet || config.list_files.is_some() || (binary && !config.count) {
+                break;
+            }
+            if config.count {
+                if config.count_matches {
+                    match_count += matcher.find_all(&line).len();
+                }
+                continue;
+            }
+            if context {
+                for (before_index, before_offset, before_line) in before.drain(..) {
+                    separate_group(out, config, last_printed, before_index)?;
+                    let line_number = config.line_number.then_some(before_index + 1);
+                    let byte_offset = config.byte_offset.then_some(before_offset);
+                    print_line(out, filename, line_number, byte_offset, '-', &display_line(&before_line, &[], config), terminator)?;
+                    last_printed = Some(before_index);
+                }
+                separate_group(out, config, last_printed, index)?;
+                last_printed = Some(index);
+                after_remaining = config.after_context;
+            }
+
+            if config.json {
+                print_json(out, filename, index + 1, &line, &matcher.find_all(&line))?;
+                continue;
+            }
+
+            let line_number = config.line_number.then_some(index + 1);
+            if config.only_matching {
+                let matches = match config.only_matching_group {
+                    0 => matcher.find_all(&line),
+                    group => matcher.find_group(&line, group),
                 };
-                
-                // Try 1 match first (greedy), then 0 matches
-                for num_matches in (0..=max_matches).rev() {
-                    if let Some(final_pos) = backtrack_match(input_chars, tokens, pos + num_matches, token_idx + 1) {
-                        return Some(final_pos);
+                // -b gives where each match starts
+                for found in matches {
+                    let matched = &line[found.start..found.end];
+                    let byte_offset = config.byte_offset.then(|| line_offset + config.encoding.input_len(&line[..found.start], invalid.as_deref()));
+                    if config.color {
+                        print_line(out, filename, line_number, byte_offset, ':', &highlight(matched, &[Match { start: 0, end: matched.len() }]), terminator)?;
+                    } else {
+                        print_line(out, filename, line_number, byte_offset, ':', matched, terminator)?;
                     }
                 }
-                None
-            },
-            _ => {
-                if pos >= input_chars.len() || !matches_token(input_chars[pos], &tokens[token_idx]) {
-                    return None;
+            } else {
+                // Only look for the matches themselves when they're needed
+                let matches = if config.color || config.byte_offset { matcher.find_all(&line) } else { Vec::new() };
+                // -b gives where the first match starts, or the line itself with -v
+                let first_start = matches.first().map_or(0, |found| found.start);
+                let byte_offset = config.byte_offset.then(|| line_offset + config.encoding.input_len(&line[..first_start], invalid.as_deref()));
+                print_line(out, filename, line_number, byte_offset, ':', &display_line(&line, &matches, config), terminator)?;
+            }
+        } else if context && !config.count {
+            if after_remaining > 0 {
+                after_remaining -= 1;
+                let line_number = config.line_number.then_some(index + 1);
+                print_line(out, filename, line_number, config.byte_offset.then_some(line_offset), '-', &display_line(&line, &[], config), terminator)?;
+                last_printed = Some(index);
+            } else if config.before_context > 0 {
+                if before.len() == config.before_context {
+                    before.pop_front();
                 }
-                backtrack_match(input_chars, tokens, pos + 1, token_idx + 1)
+                before.push_back((index, line_offset, line));
             }
         }
     }
-    
-    backtrack_match(input_chars, tokens, start_pos, 0)
+
+    let reported = if config.count_matches { match_count } else { selected_count };
+    *total_count += reported;
+    // With --total only the sum over all inputs gets printed
+    if config.count && !config.total && !config.quiet && config.list_files.is_none() {
+        print_line(out, filename, None, None, ':', &reported.to_string(), '\n')?;
+    }
+
+    Ok(selected_count)
 }
 
-fn match_pattern(input_line: &str, pattern: &str) -> bool {
-    let input_chars: Vec<char> = input_line.trim_end().chars().collect();
+// Search the file at `path`, or standard input if there's none, returning
+// whether it counts towards success: when any line was selected, or with -L
+// when none were. With -l or -L the name is printed instead of the lines when
+// the input qualifies.
+fn search_input(mut reader: impl BufRead, path: Option<&str>, show_filename: bool, matcher: &Matcher, config: &Config, out: &mut impl Write, total_count: &mut usize) -> Result<bool, SearchError> {
+    // Input with a NUL byte near the start is taken to be binary (unless -a says
+    // otherwise, or -z makes NUL the line separator). Its lines aren't printed,
+    // only whether it matched.
+    let binary = !config.text && !config.null_data &&
+        reader.fill_buf().map_err(SearchError::Read)?.contains(&0);
 
-    // Anchor detection
-    let (start_anchored, body) = if let Some(rest) = pattern.strip_prefix('^') {
-        (true, rest)
-    } else { (false, pattern) };
+    // JSON output always says which file a line came from, as null for standard input
+    let name = path.unwrap_or("(standard input)");
+    let filename = if config.json { path } else { show_filename.then_some(name) };
+    let selected = search(reader, filename, binary, matcher, config, out, total_count)? > 0;
 
-    let (end_anchored, body) = if let Some(rest) = body.strip_suffix('$') {
-        (true, rest)
-    } else { (false, body) };
+    if binary && selected && !config.quiet && !config.count && config.list_files.is_none() {
+        writeln!(out, "Binary file {} matches", name).map_err(|_| "Failed to write output")?;
+    }
 
-    let tokens = tokenize_pattern(body);
+    let listed = match config.list_files {
+        None => return Ok(selected),
+        Some(ListFiles::WithMatches) => selected,
+        Some(ListFiles::WithoutMatch) => !selected,
+    };
+    if listed && !config.quiet {
+        writeln!(out, "{}", name).map_err(|_| "Failed to write output")?;
+    }
+    Ok(listed)
+}
+
+// Whether `name` matches a glob `pattern`, where '*' stands for any run of
+// characters and '?' for any single one
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last '*' if what follows it stops matching
+    let mut star: Option<(usize, usize)> = None;
 
-    // Edge cases for anchor-only patterns
-    if start_anchored && end_anchored && tokens.is_empty() {
-        return input_chars.is_empty();
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match star {
+                // Let the '*' swallow one more character and try again
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                },
+                None => return false,
+            },
+        }
     }
-    if start_anchored && tokens.is_empty() {
-        return input_chars.is_empty();
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Whether a file found by -r should be searched, going by --include and --exclude
+fn wanted_file(config: &Config, name: &str) -> bool {
+    (config.includes.is_empty() || config.includes.iter().any(|glob| glob_matches(glob, name))) &&
+        !config.excludes.iter().any(|glob| glob_matches(glob, name))
+}
+
+// Add the path of every regular file under `dir` that `config` wants searched
+// to `files`, depth first and in name order. Symlinks aren't followed, so a
+// link back up the tree can't loop forever. Returns false if any directory
+// couldn't be read.
+fn walk_directory(dir: &str, config: &Config, files: &mut Vec<String>) -> bool {
+    let mut complete = true;
+    let mut pending = vec![PathBuf::from(dir)];
+
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Error: {}: {}", dir.display(), e);
+                complete = false;
+                continue;
+            }
+        };
+        let mut paths: Vec<PathBuf> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+        paths.sort();
+
+        let mut subdirs = Vec::new();
+        for path in paths {
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => subdirs.push(path),
+                Ok(metadata) if metadata.is_file() => {
+                    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+                    if wanted_file(config, &name) {
+                        files.push(path.display().to_string());
+                    }
+                },
+                _ => {},
+            }
+        }
+        // Pushed in reverse so they're popped in name order
+        pending.extend(subdirs.into_iter().rev());
     }
-    if end_anchored && tokens.is_empty() {
-        return input_chars.is_empty();
+    complete
+}
+
+// Print the count summed over all inputs, for -c with --total
+fn print_total(out: &mut impl Write, config: &Config, total_count: usize) -> Result<(), &'static str> {
+    if config.count && config.total && !config.quiet && config.list_files.is_none() {
+        writeln!(out, "{}", total_count).map_err(|_| "Failed to write output")?;
     }
+    Ok(())
+}
+
+// Why searching one input stopped
+enum SearchError {
+    // Reading it failed, which only rules out that input
+    Read(io::Error),
+    // Anything else, like failing to write the output, which ends the whole run
+    Other(&'static str),
+}
+
+impl From<&'static str> for SearchError {
+    fn from(e: &'static str) -> Self {
+        SearchError::Other(e)
+    }
+}
 
-    // Try matching at different starting positions
-    let start_positions: Vec<usize> = if start_anchored {
-        vec![0]
+// How a search turned out, which decides the exit code
+enum Outcome {
+    Matched,
+    NotMatched,
+    // Some input couldn't be searched, which has already been reported
+    Failed,
+}
+
+fn run() -> Result<Outcome, String> {
+    let config = parse_args(env::args().skip(1))?;
+    // Each line of a -f file is another pattern. An empty line is an empty
+    // pattern, which matches every line.
+    let mut patterns = config.patterns.clone();
+    for path in &config.pattern_files {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        patterns.extend(contents.lines().map(String::from));
+    }
+    let compile_start = Instant::now();
+    let matcher = Matcher::new(&patterns, &config.match_options)?;
+    let compile_time = compile_start.elapsed();
+    if config.debug {
+        for regex in &matcher.regexes {
+            eprintln!("{:#?}", regex);
+        }
+    }
+    // Output is buffered in blocks unless each line should show up right away:
+    // on a terminal, or with --line-buffered when piping it to something that
+    // reacts to each match
+    let mut out: Box<dyn Write> = if config.line_buffered || io::stdout().is_terminal() {
+        Box::new(LineBuffered(io::stdout().lock()))
     } else {
-        (0..=input_chars.len()).collect()
+        Box::new(BufWriter::new(io::stdout().lock()))
     };
 
-    for start_pos in start_positions {
-        if let Some(end_pos) = match_tokens_at_position(&input_chars, &tokens, start_pos) {
-            // If end-anchored, ensure we matched exactly to the end
-            if end_anchored {
-                if end_pos == input_chars.len() {
-                    return true;
-                }
-            } else {
-                return true;
-            }
-        }
+    let search_start = Instant::now();
+    let outcome = search_all(&matcher, &config, &mut out);
+    // With --timing, say how long each phase took, after all of the output
+    if config.timing {
+        out.flush().map_err(|_| "Failed to write output")?;
+        eprintln!("Compiled patterns in {:?}, searched in {:?}", compile_time, search_start.elapsed());
     }
-    
-    false
+    outcome
 }
 
-fn run() -> Result<bool, &'static str> {
-    let args: Vec<String> = env::args().collect();
-    
-    if args.len() < 3 {
-        return Err("Usage: program -E <pattern>");
+// Search each input named on the command line, or stdin if there are none
+fn search_all(matcher: &Matcher, config: &Config, out: &mut impl Write) -> Result<Outcome, String> {
+    let mut total_count = 0;
+
+    if config.paths.is_empty() {
+        let matched = match search_input(io::stdin().lock(), None, config.with_filename == Some(true), matcher, config, out, &mut total_count) {
+            Ok(matched) => matched,
+            Err(SearchError::Read(e)) => return Err(format!("(standard input): {}", e)),
+            Err(SearchError::Other(e)) => return Err(e.into()),
+        };
+        print_total(out, config, total_count)?;
+        return Ok(if matched { Outcome::Matched } else { Outcome::NotMatched });
     }
 
-    if args[1] != "-E" {
-        return Err("Expected first argument to be '-E'");
+    let mut any_matched = false;
+    let mut had_error = false;
+
+    // With -r, directories are replaced by the files under them
+    let mut paths = Vec::new();
+    for path in &config.paths {
+        if config.recursive && Path::new(path).is_dir() {
+            had_error |= !walk_directory(path, config, &mut paths);
+        } else {
+            paths.push(path.clone());
+        }
     }
 
-    let pattern = &args[2];
-    let mut input_line = String::new();
+    // Matching lines are only prefixed with their file name when searching
+    // several files, unless -H or -h says otherwise
+    let show_filename = config.with_filename.unwrap_or(paths.len() > 1 || config.recursive);
+
+    for path in &paths {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                // Report the failure but keep searching the remaining files
+                eprintln!("Error: {}: {}", path, e);
+                had_error = true;
+                continue;
+            }
+        };
+        let matched = match search_input(BufReader::new(file), Some(path), show_filename, matcher, config, out, &mut total_count) {
+            Ok(matched) => matched,
+            // Like a file that can't be opened, one that can't be read (such as
+            // a directory without -r) doesn't stop the other files being searched
+            Err(SearchError::Read(e)) => {
+                eprintln!("Error: {}: {}", path, e);
+                had_error = true;
+                continue;
+            },
+            Err(SearchError::Other(e)) => return Err(e.into()),
+        };
+        if matched {
+            any_matched = true;
+            // With -q there's nothing left to learn from the remaining files
+            if config.quiet {
+                return Ok(Outcome::Matched);
+            }
+        }
+    }
 
-    io::stdin()
-        .read_line(&mut input_line)
-        .map_err(|_| "Failed to read input")?;
+    print_total(out, config, total_count)?;
 
-    Ok(match_pattern(&input_line, pattern))
+    // Any file that couldn't be searched makes the run fail, like grep
+    Ok(if had_error {
+        Outcome::Failed
+    } else if any_matched {
+        Outcome::Matched
+    } else {
+        Outcome::NotMatched
+    })
 }
 
 // Usage: echo <input_text> | your_program.sh -E <pattern>
+//        your_program.sh -E <pattern> <file>
 fn main() {
+    // 0 when something matched, 1 when nothing did and 2 on any error, like grep
     process::exit(match run() {
-        Ok(true) => 0,
-        Ok(false) => 1,
+        Ok(Outcome::Matched) => 0,
+        Ok(Outcome::NotMatched) => 1,
+        Ok(Outcome::Failed) => 2,
         Err(e) => {
             eprintln!("Error: {}", e);
-            1
+            2
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn stream_match_never_needs_the_whole_line() {
+        let matcher = Matcher::new(&["b".to_string()], &MatchOptions::default()).unwrap();
+        // A line that never ends, so reading it whole would never finish
+        let endless = io::repeat(b'a').take(1 << 20).chain(&b"b"[..]).chain(io::repeat(b'a'));
+        assert!(matches!(stream_match(BufReader::new(endless), &matcher, '\n'), Ok(true)));
+
+        let no_match = io::repeat(b'a').take(1 << 20);
+        assert!(matches!(stream_match(BufReader::new(no_match), &matcher, '\n'), Ok(false)));
+    }
+
+    // Yields one line per read, with "needle" and "x" on line 2, and panics
+    // if asked for anything past line 3
+    struct ThreeLines(usize);
+
+    impl Read for ThreeLines {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0 += 1;
+            assert!(self.0 <= 3, "read past line 3");
+            let line: &[u8] = if self.0 == 2 { b"needle x\n" } else { b"hay\n" };
+            buf[..line.len()].copy_from_slice(line);
+            Ok(line.len())
+        }
+    }
+
+    #[test]
+    fn files_without_match_stops_at_the_first_match() {
+        // Both a general pattern and a single character, which is streamed
+        for pattern in ["needle", "x"] {
+            let args = ["-L", "-E", pattern].map(String::from);
+            let config = parse_args(args.into_iter()).unwrap();
+            let matcher = Matcher::new(&config.patterns, &config.match_options).unwrap();
+            let mut out = Vec::new();
+            let listed = search_input(BufReader::new(ThreeLines(0)), Some("big.txt"), false, &matcher, &config, &mut out, &mut 0);
+            assert!(matches!(listed, Ok(false)));
+            assert!(out.is_empty());
+        }
+    }
+
+    // Records how much had been written each time it was flushed
+    #[derive(Default)]
+    struct FlushRecorder {
+        written: Vec<u8>,
+        flushed_at: Vec<usize>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed_at.push(self.written.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn line_buffered_flushes_after_each_line() {
+        let args = ["--line-buffered", "-n", "-E", "ap"].map(String::from);
+        let config = parse_args(args.into_iter()).unwrap();
+        let matcher = Matcher::new(&config.patterns, &config.match_options).unwrap();
+        let mut out = LineBuffered(FlushRecorder::default());
+        let input = &b"apple\nberry\napricot\n"[..];
+        assert!(matches!(search_input(input, Some("fruit.txt"), false, &matcher, &config, &mut out, &mut 0), Ok(true)));
+
+        let LineBuffered(recorder) = out;
+        assert_eq!(recorder.written, b"1:apple\n3:apricot\n");
+        assert_eq!(recorder.flushed_at, [8, 18]);
+    }
+}
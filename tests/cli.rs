@@ -0,0 +1,819 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{self, Command, Output, Stdio};
+
+// Run the program with `args`, feeding it `stdin`
+fn grep(args: &[&str], stdin: &[u8]) -> Output {
+    grep_with_env(args, &[], stdin)
+}
+
+// Run the program with `args` and the environment variables in `vars` (but
+// never an inherited NO_COLOR), feeding it `stdin`
+fn grep_with_env(args: &[&str], vars: &[(&str, &str)], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_codecrafters-grep"))
+        .args(args)
+        .env_remove("NO_COLOR")
+        .envs(vars.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start the program");
+    // Searching files never reads stdin, and the program may be gone already
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+// An empty directory for one test's files
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("codecrafters-grep-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn unreadable_file_doesnt_stop_the_search() {
+    let dir = temp_dir("unreadable");
+    let file = dir.join("f.txt");
+    fs::write(&file, "hello\n").unwrap();
+    let (dir, file) = (dir.to_str().unwrap(), file.to_str().unwrap());
+
+    let output = grep(&["hello", dir, file], b"");
+    assert_eq!(stdout(&output), format!("{}:hello\n", file));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with(&format!("Error: {}: ", dir)));
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn max_count_still_prints_trailing_context() {
+    let output = grep(&["-m", "1", "-A", "1", "m"], b"m\n2\nm\n4\n");
+    assert_eq!(stdout(&output), "m\n2\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    // A line that would have matched is only context once the limit is reached
+    let output = grep(&["-n", "-m", "1", "-A", "2", "m"], b"m\nm\n3\n4\n");
+    assert_eq!(stdout(&output), "1:m\n2-m\n3-3\n");
+}
+
+#[test]
+fn byte_offsets_count_latin1_input_bytes() {
+    let output = grep(&["--encoding=latin1", "-b", "-o", "cat"], b"\xe9\xe9cat\n");
+    assert_eq!(stdout(&output), "2:cat\n");
+
+    let output = grep(&["--encoding=latin1", "-b", "cat"], b"x\n\xe9\xe9cat\n");
+    assert_eq!(stdout(&output), "4:\u{e9}\u{e9}cat\n");
+}
+
+#[test]
+fn byte_offsets_count_invalid_utf8_input_bytes() {
+    let output = grep(&["-b", "-o", "cat"], b"a\xffcat\n");
+    assert_eq!(stdout(&output), "2:cat\n");
+
+    // A U+FFFD that was in the input takes up all three of its bytes
+    let output = grep(&["-b", "-o", "cat"], b"\xef\xbf\xbd\xe2\x82cat\n");
+    assert_eq!(stdout(&output), "5:cat\n");
+}
+
+#[test]
+fn dot_wildcard() {
+    let output = grep(&["-E", "c.t"], b"cat\n");
+    assert_eq!(stdout(&output), "cat\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep(&["-E", "c.t"], b"ct\n");
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn alternation() {
+    let output = grep(&["-E", "cat|dog"], b"I have a dog\nI have a fish\n");
+    assert_eq!(stdout(&output), "I have a dog\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn searches_every_line() {
+    let output = grep(&["-E", "a+"], b"apple\nberry\nbanana\ncherry\n");
+    assert_eq!(stdout(&output), "apple\nbanana\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep(&["-E", "z"], b"apple\nberry\n");
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn prints_matching_lines_exactly() {
+    let output = grep(&["-E", "world"], b"hello world\n");
+    assert_eq!(output.stdout, b"hello world\n");
+    // The last line gets a newline even if the input didn't end with one
+    let output = grep(&["-E", "world"], b"hello world");
+    assert_eq!(output.stdout, b"hello world\n");
+}
+
+#[test]
+fn ignore_case() {
+    let output = grep(&["-i", "error"], b"Error: one\nno problem\nERROR two\n");
+    assert_eq!(stdout(&output), "Error: one\nERROR two\n");
+
+    let output = grep(&["-i", "-E", "^[A-Z]+$"], b"abc\n123\n");
+    assert_eq!(stdout(&output), "abc\n");
+
+    let output = grep(&["error"], b"ERROR\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn searches_a_file() {
+    let dir = temp_dir("file");
+    let file = dir.join("lines.txt");
+    fs::write(&file, "first apple\nsecond\nthird apple\n").unwrap();
+
+    let output = grep(&["-E", "apple", file.to_str().unwrap()], b"apple on stdin\n");
+    assert_eq!(stdout(&output), "first apple\nthird apple\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let missing = dir.join("missing.txt");
+    let output = grep(&["-E", "apple", missing.to_str().unwrap()], b"");
+    assert_eq!(stdout(&output), "");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with(&format!("Error: {}: ", missing.display())));
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn prefixes_lines_with_the_file_when_searching_several() {
+    let dir = temp_dir("several");
+    let (a, b, missing) = (dir.join("a.txt"), dir.join("b.txt"), dir.join("missing.txt"));
+    fs::write(&a, "x1\ny\n").unwrap();
+    fs::write(&b, "x2\n").unwrap();
+    let (a, b, missing) = (a.to_str().unwrap(), b.to_str().unwrap(), missing.to_str().unwrap());
+
+    let output = grep(&["x", a], b"");
+    assert_eq!(stdout(&output), "x1\n");
+
+    let output = grep(&["x", a, b], b"");
+    assert_eq!(stdout(&output), format!("{}:x1\n{}:x2\n", a, b));
+    assert_eq!(output.status.code(), Some(0));
+
+    // A file that can't be opened is reported, and the others still searched
+    let output = grep(&["x", a, missing, b], b"");
+    assert_eq!(stdout(&output), format!("{}:x1\n{}:x2\n", a, b));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with(&format!("Error: {}: ", missing)));
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn line_numbers() {
+    let output = grep(&["-n", "x"], b"x\ny\nz\nax\n");
+    assert_eq!(stdout(&output), "1:x\n4:ax\n");
+
+    // Numbering starts again in each file
+    let dir = temp_dir("line-numbers");
+    let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+    fs::write(&a, "y\nx\n").unwrap();
+    fs::write(&b, "x\n").unwrap();
+    let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+    let output = grep(&["-n", "x", a, b], b"");
+    assert_eq!(stdout(&output), format!("{}:2:x\n{}:1:x\n", a, b));
+}
+
+#[test]
+fn count() {
+    let output = grep(&["-c", "x"], b"x\ny\nxx\n");
+    assert_eq!(stdout(&output), "2\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep(&["-c", "z"], b"x\ny\n");
+    assert_eq!(stdout(&output), "0\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = grep(&["-c", "-v", "x"], b"x\ny\nz\n");
+    assert_eq!(stdout(&output), "2\n");
+
+    let dir = temp_dir("count");
+    let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+    fs::write(&a, "x\nx\n").unwrap();
+    fs::write(&b, "y\n").unwrap();
+    let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+    let output = grep(&["-c", "x", a, b], b"");
+    assert_eq!(stdout(&output), format!("{}:2\n{}:0\n", a, b));
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn invert_match() {
+    let output = grep(&["-v", "x"], b"x\ny\nax\nz\n");
+    assert_eq!(stdout(&output), "y\nz\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep(&["-v", "-n", "x"], b"x\ny\nax\nz\n");
+    assert_eq!(stdout(&output), "2:y\n4:z\n");
+
+    // Every line matching means nothing was selected
+    let output = grep(&["-v", "x"], b"x\nxx\n");
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn only_matching() {
+    let output = grep(&["-o", "-E", r"\d+"], b"abc123def456\nnone\n7\n");
+    assert_eq!(stdout(&output), "123\n456\n7\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn color() {
+    let output = grep(&["--color=always", "b+"], b"abbc\n");
+    assert_eq!(stdout(&output), "a\x1b[1;31mbb\x1b[0mc\n");
+
+    let output = grep(&["--color=never", "b+"], b"abbc\n");
+    assert_eq!(stdout(&output), "abbc\n");
+
+    // Output to a pipe isn't a terminal, so auto leaves it plain
+    let output = grep(&["--color=auto", "b+"], b"abbc\n");
+    assert_eq!(stdout(&output), "abbc\n");
+
+    let output = grep_with_env(&["--color=always", "b+"], &[("NO_COLOR", "1")], b"abbc\n");
+    assert_eq!(stdout(&output), "abbc\n");
+}
+
+#[test]
+fn keeps_trailing_whitespace() {
+    let output = grep(&["-E", "foo $"], b"foo \nfoo\n");
+    assert_eq!(stdout(&output), "foo \n");
+
+    let output = grep(&["-E", r"foo\s$"], b"foo\t\nfoo\n");
+    assert_eq!(stdout(&output), "foo\t\n");
+
+    // Only the line ending goes, including the '\r' of a CRLF
+    let output = grep(&["-E", "o$"], b"foo\r\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn unterminated_character_class() {
+    let output = grep(&["-E", "[abc"], b"abc\n");
+    assert_eq!(stdout(&output), "");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Error: Unterminated character class at position 4\n");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn lazy_quantifier() {
+    let output = grep(&["-o", "-E", "<.+?>"], b"<a><b>\n");
+    assert_eq!(stdout(&output), "<a>\n<b>\n");
+}
+
+#[test]
+fn word_regexp() {
+    let output = grep(&["-w", "-E", "cat"], b"a cat sat\ncategory\nbobcat\ncat\n(cat)\n");
+    assert_eq!(stdout(&output), "a cat sat\ncat\n(cat)\n");
+
+    // A later occurrence can qualify when an earlier one doesn't
+    let output = grep(&["-w", "-o", "-E", "cat"], b"bobcat cat\n");
+    assert_eq!(stdout(&output), "cat\n");
+
+    let output = grep(&["-w", "-E", "cat"], b"cats\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn line_regexp() {
+    let output = grep(&["-x", "-E", "cat"], b"cat\ncat sat\nthe cat\n");
+    assert_eq!(stdout(&output), "cat\n");
+
+    // Patterns that are already anchored, or alternatives, work the same way
+    let output = grep(&["-x", "-E", "^cat$"], b"cat\ncats\n");
+    assert_eq!(stdout(&output), "cat\n");
+    let output = grep(&["-x", "-E", "cat|dog"], b"cat\ndog\ncatdog\n");
+    assert_eq!(stdout(&output), "cat\ndog\n");
+}
+
+#[test]
+fn several_patterns() {
+    let output = grep(&["-e", "cat", "-e", "dog"], b"a cat\na bird\na dog\n");
+    assert_eq!(stdout(&output), "a cat\na dog\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn patterns_from_a_file() {
+    let dir = temp_dir("pattern-file");
+    let patterns = dir.join("patterns.txt");
+    fs::write(&patterns, "cat\nd.g\n").unwrap();
+    let patterns = patterns.to_str().unwrap();
+
+    let output = grep(&["-f", patterns], b"a cat\na bird\na dog\n");
+    assert_eq!(stdout(&output), "a cat\na dog\n");
+
+    // An empty pattern line matches every line
+    let with_empty = dir.join("with-empty.txt");
+    fs::write(&with_empty, "cat\n\n").unwrap();
+    let output = grep(&["-f", with_empty.to_str().unwrap()], b"a cat\na bird\n");
+    assert_eq!(stdout(&output), "a cat\na bird\n");
+}
+
+#[test]
+fn list_files() {
+    let dir = temp_dir("list-files");
+    let paths: Vec<_> = ["a.txt", "b.txt", "c.txt"].iter().map(|name| dir.join(name)).collect();
+    fs::write(&paths[0], "cat\ncat\n").unwrap();
+    fs::write(&paths[1], "dog\n").unwrap();
+    fs::write(&paths[2], "a cat\n").unwrap();
+    let paths: Vec<_> = paths.iter().map(|path| path.to_str().unwrap()).collect();
+
+    let output = grep(&[&["-l", "cat"], &paths[..]].concat(), b"");
+    assert_eq!(stdout(&output), format!("{}\n{}\n", paths[0], paths[2]));
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep(&[&["-L", "cat"], &paths[..]].concat(), b"");
+    assert_eq!(stdout(&output), format!("{}\n", paths[1]));
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep(&[&["-l", "bird"], &paths[..]].concat(), b"");
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn quiet() {
+    let output = grep(&["-q", "cat"], b"dog\ncat\ncat\n");
+    assert!(output.stdout.is_empty());
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep(&["-q", "cow"], b"dog\ncat\n");
+    assert!(output.stdout.is_empty());
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn max_count() {
+    let input = b"x1\ny\nx2\nx3\nx4\nx5\n";
+    let output = grep(&["-m", "2", "x"], input);
+    assert_eq!(stdout(&output), "x1\nx2\n");
+
+    let output = grep(&["-c", "-m", "2", "x"], input);
+    assert_eq!(stdout(&output), "2\n");
+
+    let output = grep(&["-m", "0", "x"], input);
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn context() {
+    let input = b"1\n2\n3\nmatch\n5\n6\n7\n";
+    assert_eq!(stdout(&grep(&["-A", "2", "match"], input)), "match\n5\n6\n");
+    assert_eq!(stdout(&grep(&["-B", "2", "match"], input)), "2\n3\nmatch\n");
+    assert_eq!(stdout(&grep(&["-C", "1", "match"], input)), "3\nmatch\n5\n");
+    assert_eq!(stdout(&grep(&["-n", "-C", "1", "match"], input)), "3-3\n4:match\n5-5\n");
+
+    // Groups that don't touch are separated, and ones that overlap merge
+    let input = b"a\nx\nb\nc\nd\nx\ne\nx\nf\n";
+    assert_eq!(stdout(&grep(&["-C", "1", "x"], input)), "a\nx\nb\n--\nd\nx\ne\nx\nf\n");
+}
+
+#[test]
+fn recursive() {
+    let dir = temp_dir("recursive");
+    fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+    fs::write(dir.join("top.txt"), "cat\n").unwrap();
+    fs::write(dir.join("sub/skip.txt"), "dog\n").unwrap();
+    fs::write(dir.join("sub/deeper/low.txt"), "a cat\n").unwrap();
+    // Symbolic links aren't followed, so a loop can't trap the search
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+    let dir = dir.to_str().unwrap();
+
+    let output = grep(&["-r", "cat", dir], b"");
+    let mut lines: Vec<_> = stdout(&output).lines().map(String::from).collect();
+    lines.sort();
+    assert_eq!(lines, [format!("{}/sub/deeper/low.txt:a cat", dir), format!("{}/top.txt:cat", dir)]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn end_anchor_with_and_without_final_newline() {
+    let output = grep(&["-E", "foo$"], b"foo\nfoo bar\nfoo");
+    assert_eq!(stdout(&output), "foo\nfoo\n");
+
+    let output = grep(&["-n", "-E", "o$"], b"foo\nbar\nzoo");
+    assert_eq!(stdout(&output), "1:foo\n3:zoo\n");
+}
+
+#[test]
+fn count_matches() {
+    let input = b"a a a\nb\na\n";
+    assert_eq!(stdout(&grep(&["-c", "a"], input)), "2\n");
+    assert_eq!(stdout(&grep(&["--count-matches", "a"], input)), "4\n");
+
+    let dir = temp_dir("count-matches");
+    let (one, two) = (dir.join("one.txt"), dir.join("two.txt"));
+    fs::write(&one, "aa a\n").unwrap();
+    fs::write(&two, "b\n").unwrap();
+    let (one, two) = (one.to_str().unwrap(), two.to_str().unwrap());
+    let output = grep(&["-c", "--count-matches", "a", one, two], b"");
+    assert_eq!(stdout(&output), format!("{}:3\n{}:0\n", one, two));
+}
+
+#[test]
+fn syntax_error_exit_code() {
+    let output = grep(&["-E", "abc)"], b"abc)\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Error: Unmatched ')' at position 3\n");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn empty_pattern_matches_every_line() {
+    let output = grep(&["-E", ""], b"a\n\nb\n");
+    assert_eq!(stdout(&output), "a\n\nb\n");
+
+    let output = grep(&["-E", "^$"], b"a\n\nb\n");
+    assert_eq!(stdout(&output), "\n");
+}
+
+#[test]
+fn byte_offsets() {
+    let input = b"first\nthe cat\n";
+    assert_eq!(stdout(&grep(&["-b", "cat"], input)), "10:the cat\n");
+    assert_eq!(stdout(&grep(&["-b", "-o", "[a-z]+t"], input)), "0:first\n10:cat\n");
+    // Without a match to point at, as with -v, it's where the line starts
+    assert_eq!(stdout(&grep(&["-b", "-v", "cat"], input)), "0:first\n");
+}
+
+#[test]
+fn crlf_line_endings() {
+    let input = b"foo\r\nbar\r\nfoo\r\n";
+    let output = grep(&["-E", "^foo$"], input);
+    assert_eq!(output.stdout, b"foo\nfoo\n");
+    assert_eq!(stdout(&grep(&["-c", "-E", "o$"], input)), "2\n");
+    assert_eq!(stdout(&grep(&["-c", "-E", r"\r"], input)), "0\n");
+}
+
+#[test]
+fn null_separated_records() {
+    let output = grep(&["-z", "b"], b"a\0b\nc\0bd");
+    assert_eq!(output.stdout, b"b\nc\0bd\0");
+
+    // A newline is an ordinary character, for . as well as $
+    let output = grep(&["-z", "-E", "b.c$"], b"b\nc\0bxc\nd\0");
+    assert_eq!(output.stdout, b"b\nc\0");
+}
+
+#[test]
+fn unicode_classes() {
+    let input = "café\n１２３\na\u{3000}b\n".as_bytes();
+    assert_eq!(stdout(&grep(&["-E", r"^\w+$"], input)), "");
+    assert_eq!(stdout(&grep(&["--unicode", "-E", r"^\w+$"], input)), "café\n１２３\n");
+    assert_eq!(stdout(&grep(&["--unicode", "-E", r"^\d+$"], input)), "１２３\n");
+    assert_eq!(stdout(&grep(&["--unicode", "-E", r"a\sb"], input)), "a\u{3000}b\n");
+    assert_eq!(stdout(&grep(&["-E", r"a\sb"], input)), "");
+}
+
+#[test]
+fn slurp_matches_across_lines() {
+    let input = b"start\nmiddle\nend\n";
+    assert_eq!(grep(&["-E", r"start\smiddle"], input).status.code(), Some(1));
+
+    let output = grep(&["--slurp", "-E", r"start\smiddle"], input);
+    assert_eq!(stdout(&output), "start\nmiddle\nend\n");
+    let output = grep(&["--slurp", "-o", "-E", r"t\sm"], input);
+    assert_eq!(stdout(&output), "t\nm\n");
+}
+
+#[test]
+fn exit_codes() {
+    assert_eq!(grep(&["-E", "a"], b"a\n").status.code(), Some(0));
+    assert_eq!(grep(&["-E", "b"], b"a\n").status.code(), Some(1));
+    assert_eq!(grep(&["-E", "(b"], b"a\n").status.code(), Some(2));
+    assert_eq!(grep(&["-E"], b"a\n").status.code(), Some(2));
+    assert_eq!(grep(&["--no-such-flag", "a"], b"a\n").status.code(), Some(2));
+}
+
+#[test]
+fn include_and_exclude() {
+    let dir = temp_dir("include-exclude");
+    fs::create_dir_all(dir.join("src")).unwrap();
+    for name in ["main.rs", "src/lib.rs", "Cargo.lock", "notes.txt", "src/a.md"] {
+        fs::write(dir.join(name), "needle\n").unwrap();
+    }
+    let dir = dir.to_str().unwrap();
+    let searched = |args: &[&str]| {
+        let output = grep(&[&["-r", "-l"], args, &["needle", dir]].concat(), b"");
+        let mut names: Vec<_> = stdout(&output).lines().map(|path| path[dir.len() + 1..].to_string()).collect();
+        names.sort();
+        names
+    };
+
+    assert_eq!(searched(&["--include=*.rs"]), ["main.rs", "src/lib.rs"]);
+    assert_eq!(searched(&["--exclude=*.lock", "--exclude=*.rs"]), ["notes.txt", "src/a.md"]);
+    assert_eq!(searched(&["--include=*.rs", "--include=?????.txt"]), ["main.rs", "notes.txt", "src/lib.rs"]);
+    assert_eq!(searched(&["--include=*.rs", "--exclude=lib*"]), ["main.rs"]);
+}
+
+#[test]
+fn json_output() {
+    let output = grep(&["--json", "a"], b"none\nsay \"hi\" \\ a\n");
+    assert_eq!(
+        stdout(&output),
+        concat!(
+            r#"{"file":null,"line_number":2,"line":"say \"hi\" \\ a","#,
+            r#""matches":[{"start":1,"end":2},{"start":11,"end":12}]}"#,
+            "\n",
+        ),
+    );
+
+    let output = grep(&["--json", "x"], b"\tx\x01\n");
+    assert_eq!(
+        stdout(&output),
+        r#"{"file":null,"line_number":1,"line":"\tx\u0001","matches":[{"start":1,"end":2}]}"#.to_string() + "\n",
+    );
+}
+
+#[test]
+fn binary_files() {
+    let dir = temp_dir("binary");
+    let file = dir.join("data.bin");
+    fs::write(&file, b"head\0er\ncat\n").unwrap();
+    let file = file.to_str().unwrap();
+
+    let output = grep(&["cat", file], b"");
+    assert_eq!(stdout(&output), format!("Binary file {} matches\n", file));
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep(&["-a", "cat", file], b"");
+    assert_eq!(stdout(&output), "cat\n");
+
+    assert_eq!(stdout(&grep(&["dog", file], b"")), "");
+}
+
+#[test]
+fn fixed_strings() {
+    let input = b"a.b+c\naxbbc\nA.B+C\n";
+    assert_eq!(stdout(&grep(&["-F", "a.b+c"], input)), "a.b+c\n");
+    assert_eq!(stdout(&grep(&["-E", "a.b+c"], input)), "axbbc\n");
+    assert_eq!(stdout(&grep(&["-F", "-i", "a.b+c"], input)), "a.b+c\nA.B+C\n");
+    assert_eq!(stdout(&grep(&["-F", "-x", "b+"], b"b+\nab+\n")), "b+\n");
+    assert_eq!(stdout(&grep(&["-F", "-w", "b+"], b"a b+ c\nab+\n")), "a b+ c\n");
+    assert_eq!(stdout(&grep(&["-F", "-e", "(", "-e", "*"], b"(\n)\n*\n")), "(\n*\n");
+}
+
+#[test]
+fn debug_shows_the_parsed_pattern() {
+    let output = grep(&["--debug", "-E", "a+"], b"aa\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Plus("), "{}", stderr);
+    assert!(stderr.contains("Char(\n"), "{}", stderr);
+    assert_eq!(stdout(&output), "aa\n");
+
+    let output = grep(&["--debug", "-E", "(ab)?"], b"");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Question(") && stderr.contains("Group("), "{}", stderr);
+}
+
+#[test]
+fn only_matching_group() {
+    let input = b"bob@example, amy@site and no@\n";
+    assert_eq!(stdout(&grep(&["-o1", "-E", r"(\w+)@\w+"], input)), "bob\namy\n");
+    assert_eq!(stdout(&grep(&["--only-matching=1", "-E", r"(\w+)@\w+"], input)), "bob\namy\n");
+    // Nothing is printed for a match that the group took no part in
+    assert_eq!(stdout(&grep(&["-o2", "-E", r"(\d)|([a-z])"], b"1a2\n")), "a\n");
+}
+
+#[test]
+fn total_count() {
+    let dir = temp_dir("total");
+    let first = dir.join("first.txt");
+    let second = dir.join("second.txt");
+    fs::write(&first, "apple\nbanana\napricot\n").unwrap();
+    fs::write(&second, "avocado\ncherry\n").unwrap();
+    let (first, second) = (first.to_str().unwrap(), second.to_str().unwrap());
+
+    let output = grep(&["-c", "--total", "-E", "^a", first, second], b"");
+    assert_eq!(stdout(&output), "3\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep(&["-c", "--total", "-E", "zzz", first, second], b"");
+    assert_eq!(stdout(&output), "0\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn forcing_and_suppressing_file_names() {
+    let dir = temp_dir("with-filename");
+    let first = dir.join("first.txt");
+    let second = dir.join("second.txt");
+    fs::write(&first, "apple\n").unwrap();
+    fs::write(&second, "apricot\n").unwrap();
+    let (first, second) = (first.to_str().unwrap(), second.to_str().unwrap());
+
+    let output = grep(&["-H", "-E", "ap", first], b"");
+    assert_eq!(stdout(&output), format!("{}:apple\n", first));
+
+    let output = grep(&["-H", "-E", "ap"], b"apple\n");
+    assert_eq!(stdout(&output), "(standard input):apple\n");
+
+    let output = grep(&["-h", "-E", "ap", first, second], b"");
+    assert_eq!(stdout(&output), "apple\napricot\n");
+}
+
+#[test]
+fn dotall() {
+    let output = grep(&["--slurp", "-E", "a.b"], b"a\nb\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = grep(&["--slurp", "--dotall", "-E", "a.b"], b"a\nb\n");
+    assert_eq!(stdout(&output), "a\nb\n");
+}
+
+#[test]
+fn max_columns() {
+    let output = grep(&["--max-columns", "4", "-E", "j"], "abcdéfghij\nxy\n".as_bytes());
+    assert_eq!(stdout(&output), "abcd…\n");
+
+    let output = grep(&["--max-columns", "5", "-E", "j"], "abcdéfghij\nxy\n".as_bytes());
+    assert_eq!(stdout(&output), "abcdé…\n");
+
+    let output = grep(&["--max-columns", "4", "-E", "x"], b"abcdefghij\nxy\n");
+    assert_eq!(stdout(&output), "xy\n");
+}
+
+#[test]
+fn group_separators() {
+    let input = b"a\nx\nx\nx\na\n";
+    let output = grep(&["-A", "1", "-E", "a"], input);
+    assert_eq!(stdout(&output), "a\nx\n--\na\n");
+
+    let output = grep(&["-A", "1", "--group-separator=XX", "-E", "a"], input);
+    assert_eq!(stdout(&output), "a\nx\nXX\na\n");
+
+    let output = grep(&["-A", "1", "--no-group-separator", "-E", "a"], input);
+    assert_eq!(stdout(&output), "a\nx\na\n");
+}
+
+#[test]
+fn basic_and_extended_syntax() {
+    let output = grep(&["-G", "a+"], b"aa\na+\n");
+    assert_eq!(stdout(&output), "a+\n");
+
+    let output = grep(&["-E", "a+"], b"aa\na+\nb\n");
+    assert_eq!(stdout(&output), "aa\na+\n");
+
+    // Without -E or -G the first argument is still the pattern
+    let output = grep(&["a+"], b"aa\nb\n");
+    assert_eq!(stdout(&output), "aa\n");
+}
+
+#[test]
+fn only_matching_skips_empty_matches() {
+    let output = grep(&["-o", "-E", "a*"], b"bbb\nbab\n");
+    assert_eq!(stdout(&output), "a\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn extra_word_characters() {
+    let output = grep(&["-w", "-E", "co"], b"co-op\n");
+    assert_eq!(stdout(&output), "co-op\n");
+
+    let output = grep(&["-w", "--word-chars=-", "-E", "co"], b"co-op\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = grep(&["-w", "--word-chars=-", "-E", "co-op"], b"co-op\nco-operative\n");
+    assert_eq!(stdout(&output), "co-op\n");
+
+    let output = grep(&["-o", "--word-chars=-", "-E", r"\w+"], b"co-op\n");
+    assert_eq!(stdout(&output), "co-op\n");
+}
+
+#[test]
+fn latin1_input() {
+    let high: Vec<u8> = (0x80..=0xff).chain([b'\n']).collect();
+    let decoded: String = ('\u{80}'..='\u{ff}').chain(['\n']).collect();
+    let output = grep(&["--encoding=latin1", "-E", "^.+$"], &high);
+    assert_eq!(stdout(&output), decoded);
+
+    let output = grep(&["--encoding=latin1", "-E", "caf\u{e9}"], b"caf\xe9\ncafe\n");
+    assert_eq!(stdout(&output), "caf\u{e9}\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn invalid_utf8_is_replaced() {
+    let output = grep(&["-E", "b"], b"a\xffb\nbad\nok\n");
+    assert_eq!(stdout(&output), "a\u{fffd}b\nbad\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    // The bad byte is one unknown character, not something to stop at
+    let output = grep(&["-E", "^a.b$"], b"a\xffb\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn overlapping_context_is_printed_once() {
+    // Line 3 is after the first match and before the second
+    let output = grep(&["-n", "-C", "1", "m"], b"1\nm\n3\nm\n5\n6\n");
+    assert_eq!(stdout(&output), "1-1\n2:m\n3-3\n4:m\n5-5\n");
+
+    // Windows that only touch merge too, with no separator
+    let output = grep(&["-C", "1", "m"], b"m\n2\n3\nm\n");
+    assert_eq!(stdout(&output), "m\n2\n3\nm\n");
+}
+
+#[test]
+fn tab_separated_fields() {
+    let output = grep(&["-o", "-E", r"^[^\t]*\t[^\t]*"], b"id\tname\tage\n");
+    assert_eq!(stdout(&output), "id\tname\n");
+}
+
+#[test]
+fn only_matching_conflicts() {
+    let conflicts = [
+        ("-v", "Error: -o can't be used with -v\n"),
+        ("-c", "Error: -o can't be used with -c\n"),
+        ("-l", "Error: -o can't be used with -l or -L\n"),
+        ("--count-matches", "Error: -o can't be used with --count-matches\n"),
+    ];
+    for (flag, message) in conflicts {
+        let output = grep(&["-o", flag, "-E", "a"], b"a\n");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), message);
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(stdout(&output), "");
+    }
+}
+
+#[test]
+fn absolute_anchors_in_slurp_mode() {
+    let output = grep(&["--slurp", "-E", r"\Afoo"], b"foo\nbar\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = grep(&["--slurp", "-E", r"\Afoo"], b"bar\nfoo\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    // Unlike ^ and $, which match at each line
+    let output = grep(&["--slurp", "-E", "^foo"], b"bar\nfoo\n");
+    assert_eq!(output.status.code(), Some(0));
+    let output = grep(&["--slurp", "-E", "bar$"], b"bar\nfoo\n");
+    assert_eq!(output.status.code(), Some(0));
+    let output = grep(&["--slurp", "-E", r"bar\z"], b"bar\nfoo\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn timing_goes_to_stderr() {
+    let output = grep(&["--timing", "-E", "a"], b"a\nb\n");
+    assert_eq!(stdout(&output), "a\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Compiled patterns in"));
+
+    let output = grep(&["-E", "a"], b"a\nb\n");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn invalid_back_reference_exit_code() {
+    let output = grep(&["-E", r"(a)\2"], b"aa\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Error: Invalid back reference at position 4\n");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn json_names_files_but_not_standard_input() {
+    let dir = temp_dir("json");
+    let path = dir.join("notes.txt");
+    fs::write(&path, "a\n").unwrap();
+    let path = path.to_str().unwrap();
+
+    let output = grep(&["--json", "a", path], b"");
+    assert_eq!(stdout(&output), format!("{{\"file\":{:?},\"line_number\":1,\"line\":\"a\",\"matches\":[{{\"start\":0,\"end\":1}}]}}\n", path));
+
+    // Even when -H names it elsewhere
+    let output = grep(&["--json", "-H", "a"], b"a\n");
+    assert!(stdout(&output).starts_with("{\"file\":null,"));
+}
+
+#[test]
+fn extended_flag_doesnt_take_the_pattern() {
+    let output = grep(&["-E", "-i", "a+b"], b"AAB\nb\n");
+    assert_eq!(stdout(&output), "AAB\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    // The last of -G and -E wins
+    let output = grep(&["-G", "-E", "a+"], b"aa\na+\nb\n");
+    assert_eq!(stdout(&output), "aa\na+\n");
+    let output = grep(&["-E", "-G", "a+"], b"aa\na+\nb\n");
+    assert_eq!(stdout(&output), "a+\n");
+    let output = grep(&["-E", "a+", "-G"], b"aa\na+\nb\n");
+    assert_eq!(stdout(&output), "a+\n");
+
+    let output = grep(&["-E", "-e", "a+", "-e", "b"], b"aa\nb\nc\n");
+    assert_eq!(stdout(&output), "aa\nb\n");
+}